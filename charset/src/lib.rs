@@ -0,0 +1,117 @@
+//! Conversion of uploaded texts to UTF-8, shared by the servers accepting texts in any charset.
+//!
+//! The charset comes from a byte order mark, the `charset` parameter of the content type or,
+//! failing both, is detected from the bytes themselves.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use chardetng::EncodingDetector;
+pub use encoding_rs::Encoding;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The `charset` parameter names no known encoding.
+    UnknownCharset(String),
+    /// The bytes are not valid in the encoding they were given or detected in.
+    Malformed(&'static Encoding),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownCharset(charset) => write!(f, "unsupported charset: {}", charset),
+            Error::Malformed(encoding) => write!(f, "text is not valid {}", encoding.name()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Converts `bytes` to UTF-8, borrowing them if they already are.
+pub fn decode<'b>(bytes: &'b [u8], charset: Option<&str>) -> Result<Cow<'b, str>, Error> {
+    let (encoding, bom_length) = encoding(bytes, charset)?;
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&bytes[bom_length..])
+        .ok_or(Error::Malformed(encoding))
+}
+
+/// Picks the encoding of a text starting with `bytes`, along with the length of its byte order
+/// mark.
+pub fn encoding(bytes: &[u8], charset: Option<&str>) -> Result<(&'static Encoding, usize), Error> {
+    if let Some(found) = Encoding::for_bom(bytes) {
+        return Ok(found);
+    }
+    let encoding = match charset {
+        Some(charset) => Encoding::for_label(charset.as_bytes())
+            .ok_or_else(|| Error::UnknownCharset(charset.to_owned()))?,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            detector.guess(None, true)
+        }
+    };
+    Ok((encoding, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, UTF_8, WINDOWS_1252};
+
+    #[test]
+    fn borrows_utf8() {
+        assert!(matches!(
+            decode("grüße".as_bytes(), None),
+            Ok(Cow::Borrowed("grüße"))
+        ));
+        assert_eq!(decode("grüße".as_bytes(), Some("utf-8")).unwrap(), "grüße");
+    }
+
+    #[test]
+    fn byte_order_mark_wins_over_charset() {
+        let bytes = [0xFF, 0xFE, b'h', 0, b'i', 0];
+        assert_eq!(encoding(&bytes, Some("latin1")).unwrap(), (UTF_16LE, 2));
+        assert_eq!(decode(&bytes, Some("latin1")).unwrap(), "hi");
+        assert_eq!(decode(b"\xEF\xBB\xBFhi", None).unwrap(), "hi");
+    }
+
+    #[test]
+    fn uses_charset() {
+        assert_eq!(decode(b"gr\xFC\xDFe", Some("ISO-8859-1")).unwrap(), "grüße");
+        assert_eq!(
+            encoding(b"hi", Some(" Shift_JIS ")).unwrap(),
+            (SHIFT_JIS, 0)
+        );
+    }
+
+    #[test]
+    fn detects_charset() {
+        let (bytes, _, _) = WINDOWS_1252.encode("Über die Straße gehen wir über die Brücke.");
+        assert_eq!(encoding(&bytes, None).unwrap(), (WINDOWS_1252, 0));
+        assert_eq!(
+            encoding("Über die Straße".as_bytes(), None).unwrap(),
+            (UTF_8, 0)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_charset() {
+        assert_eq!(
+            decode(b"hi", Some("klingon")),
+            Err(Error::UnknownCharset("klingon".to_owned()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert_eq!(
+            decode(b"gr\xFC\xDFe", Some("utf-8")),
+            Err(Error::Malformed(UTF_8))
+        );
+        assert_eq!(
+            Error::Malformed(UTF_8).to_string(),
+            "text is not valid UTF-8"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Read-through cache of text data in Redis.
+//!
+//! Cache failures are logged and treated as misses, so a Redis outage only
+//! sends reads to MongoDB.
+
+use log::warn;
+use observability::metrics::CACHE_COUNTER;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct Cache {
+    conn: ConnectionManager,
+    /// Keeps texts of different databases and collections apart in a shared Redis.
+    prefix: String,
+    ttl: u64,
+}
+
+impl Cache {
+    pub async fn connect(url: &str, prefix: String, ttl: u64) -> redis::RedisResult<Cache> {
+        let conn = ConnectionManager::new(redis::Client::open(url)?).await?;
+        Ok(Cache { conn, prefix, ttl })
+    }
+
+    fn key(&self, uuid: &Uuid) -> String {
+        format!("{}{}", self.prefix, uuid.as_hyphenated())
+    }
+
+    pub async fn get(&self, uuid: &Uuid) -> Option<String> {
+        let mut conn = self.conn.clone();
+        let data = match observability::redis("GET", conn.get::<_, Option<String>>(self.key(uuid)))
+            .await
+        {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("failed to read {uuid} from cache: {err}");
+                None
+            }
+        };
+        let label = if data.is_some() { "hit" } else { "miss" };
+        CACHE_COUNTER.with_label_values(&["redis", label]).inc();
+        data
+    }
+
+    pub async fn set(&self, uuid: &Uuid, data: &str) {
+        let mut conn = self.conn.clone();
+        if let Err(err) = observability::redis(
+            "SETEX",
+            conn.set_ex::<_, _, ()>(self.key(uuid), data, self.ttl),
+        )
+        .await
+        {
+            warn!("failed to cache {uuid}: {err}");
+        }
+    }
+
+    pub async fn delete(&self, uuid: &Uuid) {
+        let mut conn = self.conn.clone();
+        if let Err(err) = observability::redis("DEL", conn.del::<_, ()>(self.key(uuid))).await {
+            warn!("failed to remove {uuid} from cache: {err}");
+        }
+    }
+}
@@ -1,15 +1,21 @@
 use actix_web::{
-    delete, get, middleware::Logger, post, web, App, HttpResponse, HttpServer, Responder,
+    delete, get,
+    middleware::{from_fn, Logger},
+    post, web, App, HttpResponse, HttpServer, Responder,
 };
-use log::info;
+use cache::Cache;
+use log::{error, info};
 use mongodb::bson::doc;
-use mongodb::Client;
 use mongodb::{bson, results::DeleteResult};
+use mongodb::{Client, Collection};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-const DB_NAME: &str = "SearchApp";
-const COLL_NAME: &str = "texts";
+mod cache;
+mod config;
+mod metrics;
+mod schema;
+mod telemetry;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct MongoText {
@@ -50,15 +56,16 @@ struct Query {
 }
 
 #[post("/texts")]
-async fn save_text(client: web::Data<Client>, payload: web::Json<TextResponse>) -> impl Responder {
+async fn save_text(
+    collection: web::Data<Collection<MongoText>>,
+    payload: web::Json<TextResponse>,
+) -> impl Responder {
     let text = MongoText {
         id: Uuid::new_v4(),
         data: payload.data.to_owned(),
     };
 
-    let collection = client.database(DB_NAME).collection::<MongoText>(COLL_NAME);
-
-    match collection.insert_one(&text).await {
+    match observability::mongo("insert_one", collection.insert_one(&text)).await {
         Err(err) => {
             let response = ErrorResponse {
                 error: format!("Failed to write to DB: {err}"),
@@ -70,11 +77,21 @@ async fn save_text(client: web::Data<Client>, payload: web::Json<TextResponse>)
 }
 
 #[delete("/texts/{uuid}")]
-async fn delete_text(client: web::Data<Client>, uuid: web::Path<Uuid>) -> impl Responder {
-    let collection = client.database(DB_NAME).collection::<MongoText>(COLL_NAME);
-
-    let delete_one = collection.delete_one(doc! { "_id": uuid_to_bson(&uuid) });
-    match delete_one.await {
+async fn delete_text(
+    collection: web::Data<Collection<MongoText>>,
+    cache: web::Data<Option<Cache>>,
+    uuid: web::Path<Uuid>,
+) -> impl Responder {
+    let delete_one = observability::mongo(
+        "delete_one",
+        collection.delete_one(doc! { "_id": uuid_to_bson(&uuid) }),
+    )
+    .await;
+    // also after failures, as the text may have been deleted anyway
+    if let Some(cache) = cache.as_ref() {
+        cache.delete(&uuid).await;
+    }
+    match delete_one {
         Err(err) => {
             let response = ErrorResponse {
                 error: format!("Failed to delete from DB: {err}"),
@@ -91,10 +108,12 @@ async fn delete_text(client: web::Data<Client>, uuid: web::Path<Uuid>) -> impl R
 }
 
 #[get("/texts/{uuid}")]
-async fn get_text(client: web::Data<Client>, uuid: web::Path<Uuid>) -> impl Responder {
-    let collection = client.database(DB_NAME).collection::<MongoText>(COLL_NAME);
-    let find_one = collection.find_one(doc! { "_id": uuid_to_bson(&uuid)});
-    match find_one.await {
+async fn get_text(
+    collection: web::Data<Collection<MongoText>>,
+    cache: web::Data<Option<Cache>>,
+    uuid: web::Path<Uuid>,
+) -> impl Responder {
+    match find_data(&collection, &cache, &uuid).await {
         Err(err) => {
             let response = ErrorResponse {
                 error: format!("Failed to search DB: {err}"),
@@ -107,10 +126,8 @@ async fn get_text(client: web::Data<Client>, uuid: web::Path<Uuid>) -> impl Resp
             };
             HttpResponse::NotFound().json(response)
         }
-        Ok(Some(mongo_text)) => {
-            let response = TextResponse {
-                data: mongo_text.data,
-            };
+        Ok(Some(data)) => {
+            let response = TextResponse { data };
             HttpResponse::Ok().json(response)
         }
     }
@@ -118,7 +135,8 @@ async fn get_text(client: web::Data<Client>, uuid: web::Path<Uuid>) -> impl Resp
 
 #[get("/texts/{uuid}/search")]
 async fn search_text(
-    client: web::Data<Client>,
+    collection: web::Data<Collection<MongoText>>,
+    cache: web::Data<Option<Cache>>,
     uuid: web::Path<Uuid>,
     term: web::Query<Query>,
 ) -> impl Responder {
@@ -130,9 +148,7 @@ async fn search_text(
         return HttpResponse::BadRequest().json(response);
     }
 
-    let collection = client.database(DB_NAME).collection::<MongoText>(COLL_NAME);
-    let find_one = collection.find_one(doc! { "_id": uuid_to_bson(&uuid)});
-    match find_one.await {
+    match find_data(&collection, &cache, &uuid).await {
         Err(err) => {
             let response = ErrorResponse {
                 error: format!("Failed to search DB: {err}"),
@@ -145,15 +161,41 @@ async fn search_text(
             };
             HttpResponse::NotFound().json(response)
         }
-        Ok(Some(mongo_text)) => {
+        Ok(Some(data)) => {
             let response = FoundResponse {
-                found: mongo_text.data.contains(&term),
+                found: data.contains(&term),
             };
             HttpResponse::Ok().json(response)
         }
     }
 }
 
+/// Reads the data of a text from the cache, falling back to MongoDB and caching what it finds.
+async fn find_data(
+    collection: &Collection<MongoText>,
+    cache: &Option<Cache>,
+    uuid: &Uuid,
+) -> mongodb::error::Result<Option<String>> {
+    if let Some(data) = match cache {
+        Some(cache) => cache.get(uuid).await,
+        None => None,
+    } {
+        return Ok(Some(data));
+    }
+    let Some(mongo_text) = observability::mongo(
+        "find_one",
+        collection.find_one(doc! { "_id": uuid_to_bson(uuid)}),
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    if let Some(cache) = cache {
+        cache.set(uuid, &mongo_text.data).await;
+    }
+    Ok(Some(mongo_text.data))
+}
+
 fn uuid_to_bson(uuid: &Uuid) -> bson::Bson {
     let options = bson::ser::SerializerOptions::builder()
         .human_readable(false)
@@ -165,24 +207,75 @@ fn uuid_to_bson(uuid: &Uuid) -> bson::Bson {
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    let tracer_provider = match observability::trace::init(telemetry::SERVICE) {
+        Ok(provider) => provider,
+        Err(err) => {
+            error!("failed to create OTLP exporter: {err}");
+            std::process::exit(1);
+        }
+    };
+
     let uri = std::env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".into());
 
     info!("connecting to mongodb: {uri}");
 
     let client = Client::with_uri_str(uri).await.expect("failed to connect");
 
+    let database = client.database(&config.database);
+    if let Err(err) = schema::bootstrap(&database, &config.collection).await {
+        error!("{err}");
+        std::process::exit(1);
+    }
+
+    let collection = database.collection::<MongoText>(&config.collection);
+
+    let cache = match &config.redis_url {
+        None => None,
+        Some(url) => {
+            info!("caching texts in redis: {url}");
+            let prefix = format!("{}.{}:", config.database, config.collection);
+            match Cache::connect(url, prefix, config.cache_ttl).await {
+                Ok(cache) => Some(cache),
+                Err(err) => {
+                    error!("failed to connect to redis: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    metrics::register();
+
     info!("starting HTTP server at http://localhost:8080");
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
-            .app_data(web::Data::new(client.clone()))
+            .wrap(from_fn(metrics::track))
+            .wrap(from_fn(telemetry::trace))
+            .app_data(web::Data::new(collection.clone()))
+            .app_data(web::Data::new(cache.clone()))
             .service(save_text)
             .service(delete_text)
             .service(get_text)
             .service(search_text)
+            .service(metrics::export)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
-    .await
+    .await?;
+
+    if let Some(provider) = tracer_provider {
+        if let Err(err) = observability::trace::shutdown(provider).await {
+            error!("failed to flush traces: {err}");
+        }
+    }
+    Ok(())
 }
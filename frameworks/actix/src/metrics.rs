@@ -0,0 +1,42 @@
+//! Hooks the metrics shared with the other servers into actix: a middleware
+//! counting requests by the pattern of the route they matched and the
+//! `/metrics` route.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{get, Error, HttpResponse, Responder};
+use observability::metrics::{self, InFlight};
+
+use crate::ErrorResponse;
+
+pub use observability::metrics::{register, UNMATCHED};
+
+/// Middleware counting and timing requests.
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let in_flight = InFlight::start();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| UNMATCHED.to_owned());
+    let response = next.call(req).await;
+    let status = match &response {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    in_flight.observe(&method, &route, status.as_str());
+    response
+}
+
+#[get("/metrics")]
+pub async fn export() -> impl Responder {
+    match metrics::export() {
+        Ok(metrics) => HttpResponse::Ok()
+            .content_type(prometheus::TEXT_FORMAT)
+            .body(metrics),
+        Err(err) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: format!("Failed to encode metrics: {err}"),
+        }),
+    }
+}
@@ -0,0 +1,35 @@
+//! The layout of the actix server's texts, enforced by a validator from
+//! `schema/texts.json` when the server starts.
+//!
+//! Texts have a binary UUID as `_id` and their data in `data`. Reads go by
+//! `_id` alone, so no index is created. A collection written by axum or the
+//! Rocket searcher is refused at startup, `text-migrate` converts it.
+
+use mongodb::bson::{doc, Document};
+use mongodb::Database;
+use text_schema::ACTIX;
+
+/// Checks the layout of stored texts, then sets the validator of the collection.
+pub async fn bootstrap(db: &Database, collection: &str) -> Result<(), String> {
+    let found = db
+        .collection::<Document>(collection)
+        .find_one(ACTIX.incompatible())
+        .projection(doc! { "_id": 1 })
+        .await
+        .map_err(|err| format!("failed to check the layout of {collection}: {err}"))?;
+    ACTIX.check(collection, found.as_ref())?;
+    validate(db, collection)
+        .await
+        .map_err(|err| format!("failed to set the validator of {collection}: {err}"))
+}
+
+async fn validate(db: &Database, collection: &str) -> mongodb::error::Result<()> {
+    let exists = !db
+        .list_collection_names()
+        .filter(doc! { "name": collection })
+        .await?
+        .is_empty();
+    db.run_command(ACTIX.validate_command(collection, exists))
+        .await
+        .map(|_| ())
+}
@@ -0,0 +1,49 @@
+//! Traces the requests to actix with the spans of [`observability::trace`].
+//!
+//! Handlers run in the span of their request, so the MongoDB and Redis calls
+//! they make become its children. Routes are named by their pattern, which
+//! actix writes as `{name}` already.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::middleware::Next;
+use actix_web::Error;
+use observability::trace;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::FutureExt;
+
+/// Service name unless `OTEL_SERVICE_NAME` is set.
+pub const SERVICE: &str = "actix";
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Middleware tracing requests.
+pub async fn trace(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let cx = trace::start_request(
+        &HeaderExtractor(req.headers()),
+        req.method().as_str(),
+        req.match_pattern().as_deref(),
+        req.path(),
+    );
+    let response = next.call(req).with_context(cx.clone()).await;
+    let status = match &response {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    trace::end_request(&cx, status.as_u16());
+    response
+}
@@ -0,0 +1,69 @@
+//! Where texts are stored and cached.
+//!
+//! Settings are read from the TOML file at `CONFIG_FILE` (by default
+//! `config.toml`, if it exists) and can be overridden by `MONGODB_DATABASE`,
+//! `MONGODB_COLLECTION`, `REDIS_URL` and `CACHE_TTL`. Configurable names let
+//! several tenants share one cluster.
+
+use std::{env, fs, io};
+
+use anyhow::{anyhow, bail, Context};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: String,
+    pub collection: String,
+    /// Redis server texts are cached in, unset to read them from MongoDB every time.
+    pub redis_url: Option<String>,
+    /// Seconds texts stay cached.
+    pub cache_ttl: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database: "axum".to_owned(),
+            collection: "texts".to_owned(),
+            redis_url: None,
+            cache_ttl: 7200,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        let path = env::var("CONFIG_FILE").ok();
+        let mut config = match fs::read_to_string(path.as_deref().unwrap_or(CONFIG_FILE)) {
+            Ok(file) => toml::from_str(&file).context("invalid config file")?,
+            // only a file that was asked for explicitly has to exist
+            Err(err) if err.kind() == io::ErrorKind::NotFound && path.is_none() => {
+                Config::default()
+            }
+            Err(err) => return Err(err).context("failed to read config file"),
+        };
+        if let Ok(database) = env::var("MONGODB_DATABASE") {
+            config.database = database;
+        }
+        if let Ok(collection) = env::var("MONGODB_COLLECTION") {
+            config.collection = collection;
+        }
+        if let Ok(redis_url) = env::var("REDIS_URL") {
+            config.redis_url = Some(redis_url).filter(|url| !url.is_empty());
+        }
+        if let Ok(cache_ttl) = env::var("CACHE_TTL") {
+            config.cache_ttl = cache_ttl
+                .parse()
+                .with_context(|| format!("invalid CACHE_TTL {cache_ttl:?}"))?;
+        }
+        if config.cache_ttl == 0 {
+            bail!("cache TTL must be at least 1 second");
+        }
+        text_schema::check_names(&config.database, &config.collection)
+            .map_err(|err| anyhow!(err))?;
+        Ok(config)
+    }
+}
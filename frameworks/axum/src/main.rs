@@ -1,6 +1,8 @@
+use anyhow::Context;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::middleware;
 use axum::routing::get;
 use axum::Json;
 use axum::{http::StatusCode, routing::post, Router};
@@ -9,37 +11,64 @@ use tower_http::trace::TraceLayer;
 use std::env;
 use std::sync::Arc;
 
+mod cache;
+mod config;
 mod entries;
+mod metrics;
 mod payloads;
+mod schema;
 mod state;
+mod telemetry;
+mod upload;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let mongodb_host = env::var("MONGODB_HOST")?;
+    let config = config::Config::load()?;
 
     tracing_subscriber::fmt::init();
+    let tracer_provider =
+        observability::trace::init(telemetry::SERVICE).context("failed to create OTLP exporter")?;
 
     let client = mongodb::Client::with_uri_str(mongodb_host).await.unwrap();
 
-    let shared_state = std::sync::Arc::new(state::MongoAppState::new(client));
+    schema::bootstrap(&client.database(&config.database), &config.collection).await?;
+
+    let cache = match &config.redis_url {
+        None => None,
+        Some(url) => {
+            let prefix = format!("{}.{}:", config.database, config.collection);
+            Some(cache::Cache::connect(url, prefix, config.cache_ttl).await?)
+        }
+    };
+    metrics::register();
+
+    let shared_state = std::sync::Arc::new(state::MongoAppState::new(client, config, cache));
 
     // build our application with a single route
     let app = Router::new()
         .route("/texts", post(post_text))
-        .route("/texts/:text_id", get(get_text).delete(delete_text))
-        .route("/texts/:text_id/search", get(search_text))
+        .route("/texts/:uuid", get(get_text).delete(delete_text))
+        .route("/texts/:uuid/search", get(search_text))
+        .route("/metrics", get(metrics::export))
+        .layer(middleware::from_fn(metrics::track))
+        .layer(middleware::from_fn(telemetry::trace))
         .layer(TraceLayer::new_for_http())
         .with_state(shared_state);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
+
+    if let Some(provider) = tracer_provider {
+        observability::trace::shutdown(provider).await?;
+    }
     Ok(())
 }
 
 async fn post_text(
     State(state): State<Arc<state::MongoAppState>>,
-    Json(text_payload): Json<payloads::TextPayload>,
+    upload::TextBody(data): upload::TextBody,
 ) -> Result<
     (StatusCode, Json<payloads::InsertedResponse>),
     (StatusCode, Json<payloads::ErrorResponse>),
@@ -48,9 +77,9 @@ async fn post_text(
     let id = uuid::Uuid::new_v4();
     let entry = entries::TextEntry {
         id,
-        data: text_payload.data,
+        data,
     };
-    match state.client().insert_one(entry).await {
+    match observability::mongo("insert_one", state.client().insert_one(entry)).await {
         Ok(_) => Ok((StatusCode::CREATED, Json(payloads::InsertedResponse { id }))),
         Err(_error) => {
             Err((
@@ -74,12 +103,8 @@ async fn get_text(
             }),
         ));
     };
-    match state
-        .client()
-        .find_one(bson::to_document(&TextSearchEntry { id }).unwrap())
-        .await
-    {
-        Ok(Some(result)) => Ok(Json(payloads::TextPayload { data: result.data })),
+    match state.find_data(id).await {
+        Ok(Some(data)) => Ok(Json(payloads::TextPayload { data })),
         Ok(None) => Err((
             StatusCode::NOT_FOUND,
             Json(payloads::ErrorResponse { error: "not found" }),
@@ -104,11 +129,16 @@ async fn delete_text(
             }),
         ));
     };
-    match state
-        .client()
-        .delete_one(bson::to_document(&TextSearchEntry { id }).unwrap())
-        .await
-    {
+    let result = observability::mongo(
+        "delete_one",
+        state
+            .client()
+            .delete_one(bson::to_document(&TextSearchEntry { id }).unwrap()),
+    )
+    .await;
+    // also after failures, as the text may have been deleted anyway
+    state.invalidate(id).await;
+    match result {
         Ok(result) => {
             if result.deleted_count == 0 {
                 Err((
@@ -142,13 +172,9 @@ async fn search_text(
             }),
         ));
     };
-    match state
-        .client()
-        .find_one(bson::to_document(&TextSearchEntry { id }).unwrap())
-        .await
-    {
-        Ok(Some(result)) => {
-            let found = result.data.contains(&params.term);
+    match state.find_data(id).await {
+        Ok(Some(data)) => {
+            let found = data.contains(&params.term);
             Ok(Json(payloads::SearchResponse { found }))
         }
         Ok(None) => Err((
@@ -0,0 +1,49 @@
+//! Hooks the metrics shared with the other servers into axum: a middleware
+//! counting requests by the path they matched, its parameters written as
+//! `{name}`, and the handler of `/metrics`.
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use observability::metrics::{self, InFlight};
+
+use crate::payloads;
+
+pub use observability::metrics::{register, UNMATCHED};
+
+/// Middleware counting and timing requests.
+pub async fn track(req: Request, next: Next) -> Response {
+    let in_flight = InFlight::start();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| UNMATCHED.to_owned(), |path| pattern(path.as_str()));
+    let response = next.run(req).await;
+    in_flight.observe(&method, &route, response.status().as_str());
+    response
+}
+
+/// Writes the parameters of a route as `{name}` rather than `:name`.
+pub fn pattern(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub async fn export() -> Result<String, (StatusCode, Json<payloads::ErrorResponse>)> {
+    metrics::export().map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(payloads::ErrorResponse {
+                error: "error encoding metrics",
+            }),
+        )
+    })
+}
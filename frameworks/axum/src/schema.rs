@@ -0,0 +1,37 @@
+//! The layout of the axum server's texts, enforced by a validator from
+//! `schema/texts.json` when the server starts.
+//!
+//! Unlike the other servers, axum keeps IDs as simple UUID strings. Texts with
+//! binary IDs, or with their data in `text` as the Rocket searcher writes them,
+//! are refused at startup rather than answered with 404 one by one.
+
+use anyhow::{anyhow, Context};
+use bson::{doc, Document};
+use mongodb::Database;
+use text_schema::AXUM;
+
+/// Checks the layout of stored texts, then sets the validator of the collection.
+pub async fn bootstrap(db: &Database, collection: &str) -> anyhow::Result<()> {
+    let found = db
+        .collection::<Document>(collection)
+        .find_one(AXUM.incompatible())
+        .projection(doc! { "_id": 1 })
+        .await
+        .with_context(|| format!("failed to check the layout of {collection}"))?;
+    AXUM.check(collection, found.as_ref())
+        .map_err(|err| anyhow!(err))?;
+    validate(db, collection)
+        .await
+        .with_context(|| format!("failed to set the validator of {collection}"))
+}
+
+async fn validate(db: &Database, collection: &str) -> mongodb::error::Result<()> {
+    let exists = !db
+        .list_collection_names()
+        .filter(doc! { "name": collection })
+        .await?
+        .is_empty();
+    db.run_command(AXUM.validate_command(collection, exists))
+        .await
+        .map(|_| ())
+}
@@ -1,20 +1,49 @@
-use crate::entries::TextEntry;
-
-const MONGODB_DATABASE: &str = "axum";
-const MONGODB_COLLECTION: &str = "texts";
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::entries::{TextEntry, TextSearchEntry};
 
 pub struct MongoAppState {
     client: mongodb::Client,
+    database: String,
+    collection: String,
+    cache: Option<Cache>,
 }
 
 impl MongoAppState {
-    pub fn new(client: mongodb::Client) -> MongoAppState {
-        MongoAppState { client }
+    pub fn new(client: mongodb::Client, config: Config, cache: Option<Cache>) -> MongoAppState {
+        MongoAppState {
+            client,
+            database: config.database,
+            collection: config.collection,
+            cache,
+        }
     }
     pub fn client(&self) -> mongodb::Collection<TextEntry> {
         return self
             .client
-            .database(MONGODB_DATABASE)
-            .collection::<TextEntry>(MONGODB_COLLECTION);
+            .database(&self.database)
+            .collection::<TextEntry>(&self.collection);
+    }
+    /// Reads the data of a text from the cache, falling back to MongoDB and caching what it finds.
+    pub async fn find_data(&self, id: uuid::Uuid) -> mongodb::error::Result<Option<String>> {
+        if let Some(cache) = &self.cache {
+            if let Some(data) = cache.get(&id).await {
+                return Ok(Some(data));
+            }
+        }
+        let filter = bson::to_document(&TextSearchEntry { id }).unwrap();
+        let Some(entry) = observability::mongo("find_one", self.client().find_one(filter)).await?
+        else {
+            return Ok(None);
+        };
+        if let Some(cache) = &self.cache {
+            cache.set(&id, &entry.data).await;
+        }
+        Ok(Some(entry.data))
+    }
+    pub async fn invalidate(&self, id: uuid::Uuid) {
+        if let Some(cache) = &self.cache {
+            cache.delete(&id).await;
+        }
     }
 }
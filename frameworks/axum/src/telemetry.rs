@@ -0,0 +1,47 @@
+//! Traces the requests to axum with the spans of [`observability::trace`].
+//!
+//! Handlers run in the span of their request, so the MongoDB and Redis calls
+//! they make become its children. Routes are named by the path they matched,
+//! its parameters written as `{name}` by [`metrics::pattern`].
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+use observability::trace;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::FutureExt;
+
+use crate::metrics;
+
+/// Service name unless `OTEL_SERVICE_NAME` is set.
+pub const SERVICE: &str = "axum";
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Middleware tracing requests.
+pub async fn trace(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| metrics::pattern(path.as_str()));
+    let cx = trace::start_request(
+        &HeaderExtractor(req.headers()),
+        req.method().as_str(),
+        route.as_deref(),
+        req.uri().path(),
+    );
+    let response = next.run(req).with_context(cx.clone()).await;
+    trace::end_request(&cx, response.status().as_u16());
+    response
+}
@@ -0,0 +1,108 @@
+//! Bodies of `POST /texts` given as plain text or uploaded as a file.
+//!
+//! Unlike jakob-sample, which also extracts the text of PDF, HTML and Markdown
+//! documents, only text in any charset is accepted here.
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Multipart, Request};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::Json;
+
+use crate::payloads;
+
+/// The text of a `POST /texts` body, accepted as JSON, plain text or as the
+/// `file` field of a multipart form and converted to UTF-8.
+pub struct TextBody(pub String);
+
+type Rejection = (StatusCode, Json<payloads::ErrorResponse>);
+
+fn reject(status: StatusCode, error: &'static str) -> Rejection {
+    (status, Json(payloads::ErrorResponse { error }))
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for TextBody
+where
+    S: Send + Sync,
+{
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let (essence, charset) = parse_content_type(&content_type);
+        match essence.as_str() {
+            "application/json" => {
+                let Json(payload) = Json::<payloads::TextPayload>::from_request(req, state)
+                    .await
+                    .map_err(|rejection| reject(rejection.status(), "invalid json payload"))?;
+                Ok(TextBody(payload.data))
+            }
+            "text/plain" => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(|rejection| reject(rejection.status(), "failed to read body"))?;
+                decode(&bytes, charset).map(TextBody)
+            }
+            "multipart/form-data" => {
+                let mut multipart = Multipart::from_request(req, state)
+                    .await
+                    .map_err(|rejection| reject(rejection.status(), "invalid multipart body"))?;
+                while let Some(field) = multipart
+                    .next_field()
+                    .await
+                    .map_err(|error| reject(error.status(), "invalid multipart body"))?
+                {
+                    if field.name() != Some("file") {
+                        continue;
+                    }
+                    let (essence, charset) = parse_content_type(field.content_type().unwrap_or_default());
+                    let charset = charset.map(str::to_owned);
+                    if !matches!(essence.as_str(), "" | "text/plain" | "application/octet-stream") {
+                        return Err(reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported content type"));
+                    }
+                    let bytes = field
+                        .bytes()
+                        .await
+                        .map_err(|error| reject(error.status(), "failed to read file"))?;
+                    return decode(&bytes, charset.as_deref()).map(TextBody);
+                }
+                Err(reject(StatusCode::UNPROCESSABLE_ENTITY, "missing file field"))
+            }
+            _ => Err(reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported content type")),
+        }
+    }
+}
+
+/// Splits a content type into its lowercase essence and its `charset` parameter.
+fn parse_content_type(content_type: &str) -> (String, Option<&str>) {
+    let mut parts = content_type.split(';');
+    let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let charset = parts.find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    });
+    (essence, charset)
+}
+
+/// Converts `bytes` to UTF-8 as jakob-sample does, see `text_charset::decode`.
+fn decode(bytes: &[u8], charset: Option<&str>) -> Result<String, Rejection> {
+    text_charset::decode(bytes, charset)
+        .map(|text| text.into_owned())
+        .map_err(|error| match error {
+            text_charset::Error::UnknownCharset(_) => {
+                reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported charset")
+            }
+            text_charset::Error::Malformed(_) => {
+                reject(StatusCode::UNPROCESSABLE_ENTITY, "invalid text encoding")
+            }
+        })
+}
@@ -0,0 +1,201 @@
+use bson::spec::BinarySubtype;
+use bson::{Binary, Bson, Document};
+use clap::ValueEnum;
+use uuid::Uuid;
+
+/// How a server stores its texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Binary `_id`, data in `data`.
+    Actix,
+    /// Simple-format UUID string as `_id`, data in `data`.
+    Axum,
+    /// Binary `_id`, data in `text`.
+    Rocket,
+    /// Binary `_id`, data in `data`, as the actix server.
+    Jakob,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdFormat {
+    /// The 16 bytes of the UUID as generic binary, as `uuid_to_bson` writes them.
+    Binary,
+    /// 32 hex digits without hyphens, as `uuid::serde::simple` writes them.
+    String,
+}
+
+impl Layout {
+    pub fn id_format(self) -> IdFormat {
+        match self {
+            Layout::Axum => IdFormat::String,
+            Layout::Actix | Layout::Rocket | Layout::Jakob => IdFormat::Binary,
+        }
+    }
+
+    pub fn field(self) -> &'static str {
+        match self {
+            Layout::Rocket => "text",
+            Layout::Actix | Layout::Axum | Layout::Jakob => "data",
+        }
+    }
+
+    /// The database the server uses.
+    pub fn database(self) -> &'static str {
+        match self {
+            Layout::Actix => "SearchApp",
+            Layout::Axum => "axum",
+            Layout::Rocket => "techcamp",
+            Layout::Jakob => "erfa",
+        }
+    }
+}
+
+impl IdFormat {
+    /// BSON type name of IDs in this format, for `$type` queries.
+    pub fn bson_type(self) -> &'static str {
+        match self {
+            IdFormat::Binary => "binData",
+            IdFormat::String => "string",
+        }
+    }
+
+    fn parse(self, id: &Bson) -> Result<Uuid, String> {
+        match (self, id) {
+            // subtype 4 is accepted as well, in case texts were written by other tools
+            (
+                IdFormat::Binary,
+                Bson::Binary(Binary {
+                    subtype: BinarySubtype::Generic | BinarySubtype::Uuid,
+                    bytes,
+                }),
+            ) => Uuid::from_slice(bytes)
+                .map_err(|_| format!("binary _id of {} bytes is not a UUID", bytes.len())),
+            (IdFormat::String, Bson::String(id)) => {
+                Uuid::try_parse(id).map_err(|_| format!("_id {id:?} is not a UUID"))
+            }
+            (_, id) => Err(format!("_id {id} is not a {} UUID", self.bson_type())),
+        }
+    }
+
+    fn write(self, uuid: Uuid) -> Bson {
+        match self {
+            IdFormat::Binary => Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: uuid.as_bytes().to_vec(),
+            }),
+            IdFormat::String => Bson::String(uuid.simple().to_string()),
+        }
+    }
+}
+
+/// Converts a text from one layout to another, keeping all its other fields.
+///
+/// Fails for texts that do not hold their data as a plain string, such as
+/// compressed, encrypted or GridFS texts of the jakob server.
+pub fn convert(mut text: Document, from: Layout, to: Layout) -> Result<Document, String> {
+    let id = text.remove("_id").ok_or("text has no _id")?;
+    let uuid = from.id_format().parse(&id)?;
+    let data = match text.remove(from.field()) {
+        Some(Bson::String(data)) => data,
+        Some(_) => {
+            return Err(format!(
+                "text {id} has a `{}` that is not a string",
+                from.field()
+            ))
+        }
+        None => return Err(format!("text {id} has no `{}` field", from.field())),
+    };
+    let mut converted = Document::new();
+    converted.insert("_id", to.id_format().write(uuid));
+    converted.extend(text);
+    converted.insert(to.field(), data);
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, DateTime};
+
+    use super::*;
+
+    const UUID: Uuid = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+    const ALL: [Layout; 4] = [Layout::Actix, Layout::Axum, Layout::Rocket, Layout::Jakob];
+
+    /// A text as `layout` stores it, with an expiry as other field.
+    fn text(layout: Layout) -> Document {
+        let mut text = doc! { "_id": layout.id_format().write(UUID) };
+        text.insert("expires_at", DateTime::from_millis(1_700_000_000_000));
+        text.insert(layout.field(), "some text");
+        text
+    }
+
+    #[test]
+    fn old_shapes() {
+        assert_eq!(
+            text(Layout::Axum),
+            doc! { "_id": "0123456789abcdef0123456789abcdef", "expires_at": DateTime::from_millis(1_700_000_000_000), "data": "some text" }
+        );
+        let Some(Bson::Binary(id)) = text(Layout::Rocket).get("_id").cloned() else {
+            panic!("rocket IDs are binary");
+        };
+        assert_eq!(
+            (id.subtype, id.bytes),
+            (BinarySubtype::Generic, UUID.as_bytes().to_vec())
+        );
+        assert_eq!(text(Layout::Rocket).get_str("text"), Ok("some text"));
+    }
+
+    #[test]
+    fn converts_to_new_shape() {
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    convert(text(from), from, to),
+                    Ok(text(to)),
+                    "{from:?} to {to:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for from in ALL {
+            for to in ALL {
+                let converted = convert(text(from), from, to).unwrap();
+                assert_eq!(
+                    convert(converted, to, from),
+                    Ok(text(from)),
+                    "{from:?} to {to:?} and back"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_uuid_subtype_and_hyphens() {
+        let binary = doc! { "_id": Binary { subtype: BinarySubtype::Uuid, bytes: UUID.as_bytes().to_vec() }, "text": "some text" };
+        assert_eq!(
+            convert(binary, Layout::Rocket, Layout::Axum),
+            Ok(doc! { "_id": UUID.simple().to_string(), "data": "some text" })
+        );
+        let hyphenated = doc! { "_id": UUID.hyphenated().to_string(), "data": "some text" };
+        assert_eq!(
+            convert(hyphenated, Layout::Axum, Layout::Rocket),
+            Ok(doc! { "_id": IdFormat::Binary.write(UUID), "text": "some text" })
+        );
+    }
+
+    #[test]
+    fn rejects_texts_without_plain_data() {
+        let id = IdFormat::Binary.write(UUID);
+        assert!(convert(doc! { "data": "some text" }, Layout::Jakob, Layout::Axum).is_err());
+        assert!(convert(doc! { "_id": &id }, Layout::Jakob, Layout::Axum).is_err());
+        let compressed = doc! { "_id": &id, "compressed": { "algorithm": "zstd" } };
+        assert!(convert(compressed, Layout::Jakob, Layout::Axum).is_err());
+        let sealed = doc! { "_id": &id, "data": Binary { subtype: BinarySubtype::Generic, bytes: vec![1, 2] } };
+        assert!(convert(sealed, Layout::Jakob, Layout::Axum).is_err());
+        let string_id = doc! { "_id": UUID.simple().to_string(), "data": "some text" };
+        assert!(convert(string_id, Layout::Actix, Layout::Axum).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Converts the texts of one server into the layout of another.
+//!
+//! Texts are read in `_id` order and the last converted ID is checkpointed in
+//! the `migrations` collection of the target database, so an interrupted run
+//! continues where it stopped. Converting within one collection replaces each
+//! text and is resumable by itself, as converted texts no longer match the
+//! source layout.
+
+use anyhow::{bail, Context};
+use bson::{doc, Bson, Document};
+use clap::Parser;
+use futures::TryStreamExt;
+use layout::Layout;
+use mongodb::{Client, Collection};
+
+mod layout;
+
+const CHECKPOINTS: &str = "migrations";
+
+#[derive(Debug, Parser)]
+#[command(about = "Converts stored texts between the layouts of the servers")]
+struct Args {
+    /// Layout of the source collection.
+    #[arg(long, value_enum)]
+    from: Layout,
+    /// Layout to convert to.
+    #[arg(long, value_enum)]
+    to: Layout,
+    #[arg(long, env = "MONGODB_URI", default_value = "mongodb://localhost:27017")]
+    uri: String,
+    /// Source database, by default the one of the `--from` server.
+    #[arg(long)]
+    source_db: Option<String>,
+    #[arg(long, default_value = "texts")]
+    source_collection: String,
+    /// Target database, by default the one of the `--to` server.
+    #[arg(long)]
+    target_db: Option<String>,
+    #[arg(long, default_value = "texts")]
+    target_collection: String,
+    /// Counts and checks the texts to convert without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// Texts converted between progress reports and checkpoints.
+    #[arg(long, default_value_t = 1000)]
+    batch_size: u64,
+    /// Starts over instead of continuing from the last checkpoint.
+    #[arg(long)]
+    restart: bool,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    converted: u64,
+    skipped: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if args.from.id_format() == args.to.id_format() && args.from.field() == args.to.field() {
+        bail!(
+            "{:?} and {:?} store texts in the same layout",
+            args.from,
+            args.to
+        );
+    }
+    if args.batch_size == 0 {
+        bail!("--batch-size must be at least 1");
+    }
+    let source_db = args
+        .source_db
+        .clone()
+        .unwrap_or_else(|| args.from.database().to_owned());
+    let target_db = args
+        .target_db
+        .clone()
+        .unwrap_or_else(|| args.to.database().to_owned());
+    let in_place = source_db == target_db && args.source_collection == args.target_collection;
+
+    let client = Client::with_uri_str(&args.uri)
+        .await
+        .context("failed to connect to MongoDB")?;
+    let source = client
+        .database(&source_db)
+        .collection::<Document>(&args.source_collection);
+    let target = client
+        .database(&target_db)
+        .collection::<Document>(&args.target_collection);
+    let checkpoints = client
+        .database(&target_db)
+        .collection::<Document>(CHECKPOINTS);
+    let name = format!(
+        "{source_db}.{}->{target_db}.{}",
+        args.source_collection, args.target_collection
+    );
+
+    if args.restart && !args.dry_run {
+        checkpoints.delete_one(doc! { "_id": &name }).await?;
+    }
+    let checkpoint = match args.restart {
+        true => None,
+        false => checkpoints.find_one(doc! { "_id": &name }).await?,
+    };
+    let mut progress = Progress::default();
+    let mut filter = doc! { "$type": args.from.id_format().bson_type() };
+    if let Some(checkpoint) = &checkpoint {
+        progress.converted = checkpoint.get_i64("converted").unwrap_or(0) as u64;
+        progress.skipped = checkpoint.get_i64("skipped").unwrap_or(0) as u64;
+        if let Some(last) = checkpoint.get("last_id") {
+            filter.insert("$gt", last.clone());
+            println!("continuing {name} after {last}");
+        }
+    }
+    let mut filter = doc! { "_id": filter };
+    if in_place && args.from.id_format() == args.to.id_format() {
+        // texts converted before an interruption keep their ID
+        filter.insert(args.to.field(), doc! { "$exists": false });
+    }
+    let remaining = source.count_documents(filter.clone()).await?;
+    println!(
+        "{} {remaining} texts from {:?} to {:?} layout, {name}{}",
+        if args.dry_run {
+            "checking"
+        } else {
+            "converting"
+        },
+        args.from,
+        args.to,
+        if in_place { " in place" } else { "" },
+    );
+
+    let mut texts = source.find(filter).sort(doc! { "_id": 1 }).await?;
+    let mut done = 0;
+    let mut last_id = None;
+    while let Some(text) = texts.try_next().await? {
+        let id = text.get("_id").cloned().unwrap_or(Bson::Null);
+        match layout::convert(text, args.from, args.to) {
+            Err(reason) => {
+                eprintln!("skipping: {reason}");
+                progress.skipped += 1;
+            }
+            Ok(_) if args.dry_run => progress.converted += 1,
+            Ok(converted) => {
+                write(&source, &target, &id, converted, in_place).await?;
+                progress.converted += 1;
+            }
+        }
+        last_id = Some(id);
+        done += 1;
+        if done % args.batch_size == 0 {
+            report(done, remaining, &progress);
+            if !args.dry_run {
+                save(&checkpoints, &name, &args, last_id.as_ref(), &progress).await?;
+            }
+        }
+    }
+    report(done, remaining, &progress);
+    if !args.dry_run {
+        save(&checkpoints, &name, &args, last_id.as_ref(), &progress).await?;
+        if in_place {
+            println!(
+                "start the {:?} server to replace the schema validator of {name}",
+                args.to
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Writes a converted text, removing the original when converting in place changed its ID.
+///
+/// Upserting keeps writes idempotent, so texts written before an interruption
+/// are simply written again.
+async fn write(
+    source: &Collection<Document>,
+    target: &Collection<Document>,
+    id: &Bson,
+    converted: Document,
+    in_place: bool,
+) -> anyhow::Result<()> {
+    let new_id = converted.get("_id").cloned().unwrap_or(Bson::Null);
+    target
+        .replace_one(doc! { "_id": &new_id }, converted)
+        .upsert(true)
+        // the validator of a collection converted in place still expects the old layout
+        .bypass_document_validation(in_place)
+        .await
+        .with_context(|| format!("failed to write text {new_id}"))?;
+    if in_place && new_id != *id {
+        source
+            .delete_one(doc! { "_id": id })
+            .await
+            .with_context(|| format!("failed to remove text {id} after converting it"))?;
+    }
+    Ok(())
+}
+
+fn report(done: u64, remaining: u64, progress: &Progress) {
+    println!(
+        "{done}/{remaining} texts, {} converted, {} skipped in total",
+        progress.converted, progress.skipped
+    );
+}
+
+async fn save(
+    checkpoints: &Collection<Document>,
+    name: &str,
+    args: &Args,
+    last_id: Option<&Bson>,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    let Some(last_id) = last_id else {
+        return Ok(());
+    };
+    let checkpoint = doc! {
+        "from": format!("{:?}", args.from),
+        "to": format!("{:?}", args.to),
+        "last_id": last_id,
+        "converted": progress.converted as i64,
+        "skipped": progress.skipped as i64,
+        "updated_at": bson::DateTime::now(),
+    };
+    checkpoints
+        .replace_one(doc! { "_id": name }, checkpoint)
+        .upsert(true)
+        .await
+        .context("failed to save the checkpoint")?;
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! Names of the database and collection texts are stored in.
+//!
+//! They are read like any other Rocket setting, from `Rocket.toml` or from
+//! `ROCKET_MONGO_DATABASE` and `ROCKET_MONGO_COLLECTION`, so several tenants
+//! can share one cluster.
+
+use rocket::serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Config {
+    #[serde(default = "default_database")]
+    pub mongo_database: String,
+    #[serde(default = "default_collection")]
+    pub mongo_collection: String,
+}
+
+fn default_database() -> String {
+    "techcamp".to_owned()
+}
+
+fn default_collection() -> String {
+    "texts".to_owned()
+}
+
+impl Config {
+    /// Checks the restrictions MongoDB puts on database and collection names.
+    pub fn validate(&self) -> Result<(), String> {
+        text_schema::check_names(&self.mongo_database, &self.mongo_collection)
+    }
+}
@@ -1,14 +1,63 @@
+mod config;
 mod routes;
+mod schema;
 
 #[macro_use]
 extern crate rocket;
 
+use observability::metrics;
+use observability::rocket::{export, traced, tracing, Track};
+use rocket::fairing::{self, AdHoc};
+use rocket::{Build, Rocket};
 use rocket_db_pools::Database;
 use routes::*;
 
+/// Service name of the traces unless `OTEL_SERVICE_NAME` is set.
+const SERVICE: &str = "rocket";
+
+/// Reads the database and collection names, then sets the validator of the texts collection,
+/// refusing to start on texts stored in another layout.
+async fn bootstrap_schema(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract::<config::Config>() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("invalid config: {e}");
+            return Err(rocket);
+        }
+    };
+    if let Err(e) = config.validate() {
+        error!("invalid config: {e}");
+        return Err(rocket);
+    }
+    let Some(db) = TextsDatabase::fetch(&rocket) else {
+        return Err(rocket);
+    };
+    let database = db.database(&config.mongo_database);
+    match schema::bootstrap(&database, &config.mongo_collection).await {
+        Ok(()) => Ok(rocket.manage(config)),
+        Err(e) => {
+            error!("{e}");
+            Err(rocket)
+        }
+    }
+}
+
 #[launch]
 fn rocket() -> _ {
+    metrics::register();
     rocket::build()
+        .attach(Track)
+        .attach(tracing(SERVICE))
         .attach(TextsDatabase::init())
-        .mount("/", routes![get_text, post_text, delete_text, get_search])
+        .attach(AdHoc::try_on_ignite("Mongo schema", bootstrap_schema))
+        .mount(
+            "/",
+            traced(routes![
+                get_text,
+                post_text,
+                delete_text,
+                get_search,
+                export
+            ]),
+        )
 }
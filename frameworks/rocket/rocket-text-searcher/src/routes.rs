@@ -6,8 +6,11 @@ use rocket::serde::{
     json::{json, Json, Value},
     Deserialize, Serialize,
 };
+use rocket::State;
 use rocket_db_pools::{mongodb, Connection, Database};
 
+use crate::config::Config;
+
 #[derive(Database)]
 #[database("texts")]
 pub struct TextsDatabase(mongodb::Client);
@@ -25,6 +28,11 @@ pub struct Text {
     text: String,
 }
 
+fn texts(db: &mongodb::Client, config: &Config) -> mongodb::Collection<Text> {
+    db.database(&config.mongo_database)
+        .collection::<Text>(&config.mongo_collection)
+}
+
 #[allow(deprecated)]
 fn uuid_to_bson(uuid: &Uuid) -> mongodb::bson::Bson {
     // Despite this being deprecated it is currently necessary to make the webserver work,
@@ -34,15 +42,19 @@ fn uuid_to_bson(uuid: &Uuid) -> mongodb::bson::Bson {
 }
 
 #[post("/texts", format = "application/json", data = "<msg>")]
-pub async fn post_text(db: Connection<TextsDatabase>, msg: Json<Message<'_>>) -> (Status, Value) {
+pub async fn post_text(
+    db: Connection<TextsDatabase>,
+    config: &State<Config>,
+    msg: Json<Message<'_>>,
+) -> (Status, Value) {
     let id = Uuid::new_v4();
-    let collection = db.database("techcamp").collection::<Text>("texts");
+    let collection = texts(&db, config);
     let new_text = Text {
         _id: id,
         text: msg.data.to_string(),
     };
 
-    match collection.insert_one(new_text, None).await {
+    match observability::mongo("insert_one", collection.insert_one(new_text, None)).await {
         Ok(_) => (Status::Created, json!({"id": id})),
         Err(e) => (
             Status::InternalServerError,
@@ -52,11 +64,17 @@ pub async fn post_text(db: Connection<TextsDatabase>, msg: Json<Message<'_>>) ->
 }
 
 #[delete("/texts/<uuid>")]
-pub async fn delete_text(db: Connection<TextsDatabase>, uuid: Uuid) -> (Status, Value) {
-    let collection = db.database("techcamp").collection::<Text>("texts");
-    match collection
-        .delete_one(doc! { "_id": uuid_to_bson(&uuid)}, None)
-        .await
+pub async fn delete_text(
+    db: Connection<TextsDatabase>,
+    config: &State<Config>,
+    uuid: Uuid,
+) -> (Status, Value) {
+    let collection = texts(&db, config);
+    match observability::mongo(
+        "delete_one",
+        collection.delete_one(doc! { "_id": uuid_to_bson(&uuid)}, None),
+    )
+    .await
     {
         Err(e) => (
             Status::InternalServerError,
@@ -72,17 +90,24 @@ pub async fn delete_text(db: Connection<TextsDatabase>, uuid: Uuid) -> (Status,
 
 async fn get_from_database(
     db: Connection<TextsDatabase>,
+    config: &Config,
     uuid: Uuid,
 ) -> mongodb::error::Result<Option<Text>> {
-    let collection = db.database("techcamp").collection::<Text>("texts");
-    collection
-        .find_one(doc! { "_id": uuid_to_bson(&uuid)}, None)
-        .await
+    let collection = texts(&db, config);
+    observability::mongo(
+        "find_one",
+        collection.find_one(doc! { "_id": uuid_to_bson(&uuid)}, None),
+    )
+    .await
 }
 
 #[get("/texts/<uuid>")]
-pub async fn get_text(db: Connection<TextsDatabase>, uuid: Uuid) -> (Status, Value) {
-    match get_from_database(db, uuid).await {
+pub async fn get_text(
+    db: Connection<TextsDatabase>,
+    config: &State<Config>,
+    uuid: Uuid,
+) -> (Status, Value) {
+    match get_from_database(db, config, uuid).await {
         Err(e) => (
             Status::InternalServerError,
             json!({"error": format!("error searching database: {e}")}),
@@ -98,8 +123,13 @@ pub async fn get_text(db: Connection<TextsDatabase>, uuid: Uuid) -> (Status, Val
 }
 
 #[get("/texts/<uuid>/search?<term>")]
-pub async fn get_search(db: Connection<TextsDatabase>, uuid: Uuid, term: &str) -> (Status, Value) {
-    match get_from_database(db, uuid).await {
+pub async fn get_search(
+    db: Connection<TextsDatabase>,
+    config: &State<Config>,
+    uuid: Uuid,
+    term: &str,
+) -> (Status, Value) {
+    match get_from_database(db, config, uuid).await {
         Err(e) => (
             Status::InternalServerError,
             json!({"error": format!("error searching database: {e}")}),
@@ -0,0 +1,37 @@
+//! The layout of the searcher's texts, enforced by a validator from
+//! `schema/texts.json` when the server starts.
+//!
+//! The searcher is the only server keeping the data of a text in `text`
+//! rather than `data`. As searches scan a single text by `_id`, the default
+//! index suffices.
+
+use rocket_db_pools::mongodb::bson::{doc, Document};
+use rocket_db_pools::mongodb::options::FindOneOptions;
+use rocket_db_pools::mongodb::{self, Database};
+use text_schema::ROCKET;
+
+/// Checks the layout of stored texts, then sets the validator of the collection.
+pub async fn bootstrap(db: &Database, collection: &str) -> Result<(), String> {
+    let options = FindOneOptions::builder()
+        .projection(doc! { "_id": 1 })
+        .build();
+    let found = db
+        .collection::<Document>(collection)
+        .find_one(ROCKET.incompatible(), options)
+        .await
+        .map_err(|e| format!("failed to check the layout of {collection}: {e}"))?;
+    ROCKET.check(collection, found.as_ref())?;
+    validate(db, collection)
+        .await
+        .map_err(|e| format!("failed to set the validator of {collection}: {e}"))
+}
+
+async fn validate(db: &Database, collection: &str) -> mongodb::error::Result<()> {
+    let exists = !db
+        .list_collection_names(doc! { "name": collection })
+        .await?
+        .is_empty();
+    db.run_command(ROCKET.validate_command(collection, exists), None)
+        .await
+        .map(|_| ())
+}
@@ -0,0 +1,174 @@
+#!/usr/bin/env bash
+# Checks how new texts are cached under each cache_policy, including failed inserts
+# and retries of them with the same idempotency key.
+#
+# Runs the server against a throwaway local Redis (or any stand-in speaking its
+# protocol, e.g. REDIS_SERVER=valkey-server) and a local mongod with test
+# commands enabled, which inserts are made to fail with.
+#
+#   ./cache-tests.sh
+set -euo pipefail
+cd "$(dirname "$0")"
+
+REDIS_SERVER=${REDIS_SERVER:-redis-server}
+REDIS_PORT=${REDIS_PORT:-6390}
+MONGO_PORT=${MONGO_PORT:-27090}
+PORT=${PORT:-8090}
+URL=http://127.0.0.1:$PORT
+
+work=$(mktemp -d)
+pids=()
+cleanup() {
+    for pid in "${pids[@]}"; do kill "$pid" 2>/dev/null || true; done
+    wait 2>/dev/null || true
+    rm -rf "$work"
+}
+trap cleanup EXIT
+
+failures=0
+check() {
+    local name=$1 expected=$2 actual=$3
+    if [ "$expected" = "$actual" ]; then
+        echo "ok   $name"
+    else
+        echo "FAIL $name: expected $expected, got $actual"
+        failures=$((failures + 1))
+    fi
+}
+
+wait_for() {
+    for _ in $(seq 100); do
+        if "$@" >/dev/null 2>&1; then return 0; fi
+        sleep 0.1
+    done
+    echo "timed out waiting for: $*" >&2
+    return 1
+}
+
+redis() {
+    redis-cli -p "$REDIS_PORT" "$@"
+}
+
+mongo() {
+    mongosh --quiet --port "$MONGO_PORT" erfa --eval "$1"
+}
+
+# whether a text is stored, under its ID as generic binary
+stored() {
+    [ "$(mongo "db.texts.countDocuments({ _id: Binary.createFromHexString('${1//-/}', 0) })")" = 1 ]
+}
+
+# Redis key a text is cached under, prefixed with the database and collection of the texts
+key() {
+    echo "erfa.texts:$1"
+}
+
+evicted() {
+    [ "$(redis exists "$(key "$1")")" = 0 ]
+}
+
+released() {
+    [ "$(redis exists "$(key "idempotency:$1")")" = 0 ]
+}
+
+# makes the next insert into MongoDB fail
+fail_next_insert() {
+    mongo 'db.adminCommand({ configureFailPoint: "failCommand", mode: { times: 1 }, data: { failCommands: ["insert"], errorCode: 2 } })' >/dev/null
+}
+
+server_pid=
+start_server() {
+    ROCKET_PORT=$PORT \
+    ROCKET_CACHE_POLICY=$1 \
+    ROCKET_MEMORY_CACHE_BYTES=0 \
+    ROCKET_NEGATIVE_CACHE_TTL=0 \
+    ROCKET_DATABASES="{redis={url=\"redis://127.0.0.1:$REDIS_PORT\"},mongo={url=\"mongodb://127.0.0.1:$MONGO_PORT\"}}" \
+        ./target/debug/ho-erfa-sample >"$work/server-$1.log" 2>&1 &
+    server_pid=$!
+    pids+=("$server_pid")
+    wait_for curl -sf "$URL/metrics"
+}
+
+stop_server() {
+    kill "$server_pid"
+    wait "$server_pid" 2>/dev/null || true
+}
+
+# posts a text, printing the status and the ID; further arguments are passed on to curl
+post() {
+    curl -s -o "$work/body" -w '%{http_code}' -X POST "$URL/texts" -d '{"data":"hello"}' -H 'Content-Type: application/json' "$@"
+    echo " $(sed -n 's/.*"id":"\([^"]*\)".*/\1/p' "$work/body")"
+}
+
+status() {
+    curl -s -o /dev/null -w '%{http_code}' -X "$1" "$URL$2"
+}
+
+cargo build
+"$REDIS_SERVER" --port "$REDIS_PORT" --save '' --appendonly no >"$work/redis.log" 2>&1 &
+pids+=($!)
+mkdir "$work/mongo"
+mongod --port "$MONGO_PORT" --dbpath "$work/mongo" --setParameter enableTestCommands=1 >"$work/mongo.log" 2>&1 &
+pids+=($!)
+wait_for redis ping
+wait_for mongo 'db.runCommand({ ping: 1 })'
+
+start_server write-through
+read -r code id < <(post)
+check "write-through: created" 201 "$code"
+check "write-through: cached once stored" hello "$(redis get "$(key "$id")")"
+check "write-through: readable" 200 "$(status GET "/texts/$id")"
+redis flushall >/dev/null
+fail_next_insert
+read -r code id < <(post)
+check "write-through: failed insert" 500 "$code"
+check "write-through: nothing cached after failed insert" 0 "$(redis dbsize)"
+check "delete: missing text" 404 "$(status DELETE /texts/013391b2-cfe6-40d1-b501-ee4bd2434001)"
+stop_server
+
+start_server write-around
+read -r code id < <(post)
+check "write-around: created" 201 "$code"
+check "write-around: not cached when stored" 0 "$(redis exists "$(key "$id")")"
+check "write-around: readable" 200 "$(status GET "/texts/$id")"
+check "write-around: cached once read" hello "$(redis get "$(key "$id")")"
+redis flushall >/dev/null
+fail_next_insert
+read -r code id < <(post)
+check "write-around: failed insert" 500 "$code"
+check "write-around: nothing cached after failed insert" 0 "$(redis dbsize)"
+stop_server
+
+start_server write-behind
+read -r code id < <(post)
+check "write-behind: created" 201 "$code"
+check "write-behind: cached right away" hello "$(redis get "$(key "$id")")"
+wait_for stored "$id" || true
+redis flushall >/dev/null
+check "write-behind: readable once stored" 200 "$(status GET "/texts/$id")"
+redis flushall >/dev/null
+fail_next_insert
+read -r code id < <(post)
+check "write-behind: confirmed before failed insert" 201 "$code"
+wait_for evicted "$id" || true
+check "write-behind: evicted after failed insert" 0 "$(redis exists "$(key "$id")")"
+check "write-behind: missing after failed insert" 404 "$(status GET "/texts/$id")"
+redis flushall >/dev/null
+fail_next_insert
+read -r code id < <(post -H 'Idempotency-Key: behind')
+check "write-behind: confirmed with idempotency key before failed insert" 201 "$code"
+wait_for released behind || true
+read -r code retried < <(post -H 'Idempotency-Key: behind')
+check "write-behind: retry after failed insert not replayed" 201 "$code"
+check "write-behind: retry after failed insert gets a new ID" 1 "$([ "$retried" != "$id" ] && echo 1)"
+wait_for stored "$retried" || true
+check "write-behind: retry after failed insert readable" 200 "$(status GET "/texts/$retried")"
+stop_server
+
+if [ "$failures" -gt 0 ]; then
+    echo "$failures checks failed, server logs are in $work" >&2
+    trap - EXIT
+    for pid in "${pids[@]}"; do kill "$pid" 2>/dev/null || true; done
+    exit 1
+fi
+echo "all checks passed"
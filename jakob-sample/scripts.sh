@@ -1,3 +1,14 @@
 curl -X POST http://localhost:8080/texts -d '{"data":"hello"}' -H 'Content-Type: application/json'
+curl -X POST http://localhost:8080/texts -d '{"data":"hello","ttl_seconds":60}' -H 'Content-Type: application/json'
+curl -X PUT http://localhost:8080/texts/0191e5a6-7d2c-7e4b-9c1a-3f2b8d4e6a10 -d '{"data":"hello"}' -H 'Content-Type: application/json'
+curl -X POST http://localhost:8080/texts/bulk --data-binary $'{"data":"hello"}\n{"data":"world"}\n' -H 'Content-Type: application/x-ndjson'
+curl -X POST http://localhost:8080/texts -F 'file=@../spec.pdf;type=application/pdf'
+curl -X POST http://localhost:8080/texts --data-binary @book.txt -H 'Content-Type: text/plain; charset=utf-8'
+curl -X GET http://localhost:8080/texts/export -H 'Accept-Encoding: gzip' -o texts.ndjson.gz
+curl -X GET http://localhost:8080/texts/0191e5a6-7d2c-7e4b-9c1a-3f2b8d4e6a10/versions
+curl -X GET http://localhost:8080/texts/0191e5a6-7d2c-7e4b-9c1a-3f2b8d4e6a10/versions/1
+curl -X GET 'http://localhost:8080/texts/0191e5a6-7d2c-7e4b-9c1a-3f2b8d4e6a10/diff?from=1&to=2'
 curl -X GET http://localhost:8080/metrics
+curl -X GET "http://localhost:8080/stats/top-terms?limit=20"
 curl -X GET http://localhost:8080/texts/013391b2-cfe6-40d1-b501-ee4bd2434001
+curl -X POST http://localhost:8080/admin/reencrypt -H 'Authorization: Bearer change-me'
@@ -0,0 +1,279 @@
+//! Operations for administrators, authorized by the configured `admin_token`.
+
+use rocket::futures::TryStreamExt;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket::State;
+use rocket_db_pools::mongodb::bson::{self, doc, oid::ObjectId, Bson, Document};
+use rocket_db_pools::mongodb::options::{FindOneOptions, FindOptions};
+use rocket_db_pools::mongodb::{self, Collection};
+use rocket_db_pools::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{self, Keyring, WrappedKey};
+use crate::{discard, encrypt, history, original, uuid_to_bson, AppConfig, Db, Store, Text};
+
+/// A request carrying the admin token as its bearer token.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(expected) = req.rocket().state::<AppConfig>().and_then(|config| config.admin_token.as_deref()) else {
+            return request::Outcome::Error((Status::Forbidden, ()));
+        };
+        let token = req.headers().get_one("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        // comparing digests keeps the time taken independent of where the tokens differ
+        match token {
+            Some(token) if Sha256::digest(token) == Sha256::digest(expected) => request::Outcome::Success(Admin),
+            _ => request::Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[catch(401)]
+pub fn unauthorized() -> Value {
+    json!({ "error": "missing or invalid admin token" })
+}
+
+#[catch(403)]
+pub fn forbidden() -> Value {
+    json!({ "error": "admin operations are disabled" })
+}
+
+/// Re-encrypts every text, the earlier versions and the originals of texts, under the active master key.
+///
+/// Texts stored before encryption was turned on are encrypted, for the others
+/// only the data keys are wrapped again, which is cheap regardless of text sizes.
+/// Texts that fail are counted and left for another run, which skips the texts
+/// already done. Cached data sealed under an older master key is read from the
+/// DB again once that key is removed from the keyring, cached plain data expires.
+#[post("/admin/reencrypt")]
+pub async fn reencrypt(
+    _admin: Admin,
+    mongo: Connection<Store>,
+    config: &State<AppConfig>,
+) -> (Status, Value) {
+    let Some(active) = config.keys.active() else {
+        return (Status::Conflict, json!({ "error": "no encryption key is active" }));
+    };
+    let db = config.database(&mongo);
+    let mut counts = Counts::default();
+    let encrypted = async {
+        encrypt_texts(&db, config, &mut counts).await?;
+        encrypt_versions(&db, &config.keys, &mut counts).await?;
+        encrypt_originals(&db, config, &mut counts).await
+    };
+    if let Err(error) = encrypted.await {
+        return (Status::InternalServerError, json!({
+            "error": format!("failed to read from DB: {}", error),
+            "encrypted": counts.encrypted,
+            "reencrypted": counts.reencrypted,
+            "failed": counts.failed,
+        }));
+    }
+    let collections = [
+        (config.mongo_collection.clone(), "key"),
+        (db.collection_name(history::COLLECTION), "text.key"),
+        (db.collection_name(original::COLLECTION), "key"),
+    ];
+    for (collection, field) in collections {
+        if let Err(error) = rewrap(&config.keys, &db.collection(&collection), active, field, &mut counts).await {
+            return (Status::InternalServerError, json!({
+                "error": format!("failed to read from DB: {}", error),
+                "encrypted": counts.encrypted,
+                "reencrypted": counts.reencrypted,
+                "failed": counts.failed,
+            }));
+        }
+    }
+    (Status::Ok, json!({
+        "key_id": active,
+        "encrypted": counts.encrypted,
+        "reencrypted": counts.reencrypted,
+        "failed": counts.failed,
+    }))
+}
+
+#[derive(Default)]
+struct Counts {
+    encrypted: u64,
+    reencrypted: u64,
+    failed: u64,
+}
+
+/// Encrypts the texts stored in plain.
+async fn encrypt_texts(db: &Db, config: &AppConfig, counts: &mut Counts) -> mongodb::error::Result<()> {
+    let texts = config.texts(db);
+    let mut cursor = observability::mongo("find", texts.find(doc! { "key": { "$exists": false } }, None)).await?;
+    while let Some(text) = cursor.try_next().await? {
+        let id = text._id;
+        // a text replaced in the meantime may be encrypted already
+        let filter = doc! {
+            "_id": uuid_to_bson(&id),
+            "key": { "$exists": false },
+            "version": text.version,
+            "modified_at": text.modified_at,
+        };
+        let (text, plain) = match encrypt(db, &config.keys, text).await {
+            Ok(encrypted) => encrypted,
+            Err(error) => {
+                warn!("failed to encrypt text {}: {}", id, error);
+                counts.failed += 1;
+                continue;
+            },
+        };
+        let replaced = observability::mongo("replace_one", texts.replace_one(filter, &text, None)).await;
+        settle(db, &text, &plain, replaced.map(|result| result.modified_count), counts).await;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PlainVersion {
+    _id: ObjectId,
+    text: Text,
+}
+
+/// Encrypts the earlier versions of texts stored in plain, each under a data key of its own.
+async fn encrypt_versions(db: &Db, keys: &Keyring, counts: &mut Counts) -> mongodb::error::Result<()> {
+    let versions = db.collection::<PlainVersion>(&db.collection_name(history::COLLECTION));
+    let filter = doc! { "text": { "$exists": true }, "text.key": { "$exists": false } };
+    let mut cursor = observability::mongo("find", versions.find(filter, None)).await?;
+    while let Some(version) = cursor.try_next().await? {
+        let id = version.text._id;
+        let (text, plain) = match encrypt(db, keys, version.text).await {
+            Ok(encrypted) => encrypted,
+            Err(error) => {
+                warn!("failed to encrypt version {} of {}: {}", version._id, id, error);
+                counts.failed += 1;
+                continue;
+            },
+        };
+        let filter = doc! { "_id": version._id, "text.key": { "$exists": false } };
+        let update = doc! { "$set": { "text": bson::to_bson(&text).expect("text is serializable") } };
+        let updated = observability::mongo("update_one", versions.update_one(filter, update, None)).await;
+        settle(db, &text, &plain, updated.map(|result| result.modified_count), counts).await;
+    }
+    Ok(())
+}
+
+/// Releases the storage of a text that was encrypted, whichever of its plain and encrypted form is not stored.
+async fn settle(db: &Db, text: &Text, plain: &Text, modified: mongodb::error::Result<u64>, counts: &mut Counts) {
+    match modified {
+        Ok(modified) if modified > 0 => {
+            counts.encrypted += 1;
+            discard(db, plain).await;
+            return;
+        },
+        Ok(_) => {},
+        Err(error) => {
+            warn!("failed to encrypt text {}: {}", text._id, error);
+            counts.failed += 1;
+        },
+    }
+    // only the file of a text in GridFS was written anew
+    if plain.file.is_some() {
+        discard(db, &Text { _id: text._id, file: text.file, ..Default::default() }).await;
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PlainOriginal {
+    _id: Uuid,
+    data: bson::Binary,
+}
+
+/// Seals the originals stored in plain under the data keys of their texts, once those are encrypted.
+async fn encrypt_originals(db: &Db, config: &AppConfig, counts: &mut Counts) -> mongodb::error::Result<()> {
+    let originals = db.collection::<PlainOriginal>(&db.collection_name(original::COLLECTION));
+    let mut cursor = observability::mongo("find", originals.find(doc! { "key": { "$exists": false } }, None)).await?;
+    while let Some(original) = cursor.try_next().await? {
+        let id = original._id;
+        let options = FindOneOptions::builder().projection(doc! { "_id": 1, "key": 1 }).build();
+        let text = observability::mongo("find_one", config.texts(db).find_one(doc! { "_id": uuid_to_bson(&id) }, options)).await?;
+        // the text is gone, or failed to be encrypted and is counted already
+        let Some(key) = text.and_then(|text| text.key) else {
+            continue;
+        };
+        let sealed = match config.keys.unwrap(&key, &id).and_then(|data_key| data_key.seal(&original.data.bytes)) {
+            Ok(sealed) => crypto::binary(sealed),
+            Err(error) => {
+                warn!("failed to encrypt original of {}: {}", id, error);
+                counts.failed += 1;
+                continue;
+            },
+        };
+        let filter = doc! { "_id": uuid_to_bson(&id), "key": { "$exists": false } };
+        let update = doc! { "$set": { "data": sealed, "key": bson::to_bson(&key).expect("key is serializable") } };
+        match observability::mongo("update_one", originals.update_one(filter, update, None)).await {
+            Ok(result) => counts.encrypted += result.modified_count,
+            Err(error) => {
+                warn!("failed to encrypt original of {}: {}", id, error);
+                counts.failed += 1;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Wraps the data keys at `field` of all documents in a collection under the active master key.
+async fn rewrap(
+    keys: &Keyring,
+    collection: &Collection<Document>,
+    active: &str,
+    field: &str,
+    counts: &mut Counts,
+) -> mongodb::error::Result<()> {
+    let key_id = format!("{}.key_id", field);
+    let filter = doc! { field: { "$exists": true }, &key_id: { "$ne": active } };
+    let options = FindOptions::builder().projection(doc! { "_id": 1, field: 1 }).build();
+    let mut cursor = observability::mongo("find", collection.find(filter, options)).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+        let wrapped = match wrapped_key(&document, field) {
+            Some(wrapped) => wrapped,
+            None => {
+                warn!("failed to re-encrypt key of {} in {}: malformed key", id, collection.name());
+                counts.failed += 1;
+                continue;
+            },
+        };
+        let rewrapped = match keys.rewrap(&wrapped) {
+            Ok(rewrapped) => rewrapped,
+            Err(error) => {
+                warn!("failed to re-encrypt key of {} in {}: {}", id, collection.name(), error);
+                counts.failed += 1;
+                continue;
+            },
+        };
+        let rewrapped = bson::to_bson(&rewrapped).expect("key is serializable");
+        // a document replaced in the meantime already has a new key
+        let filter = doc! { "_id": &id, &key_id: &wrapped.key_id };
+        match observability::mongo("update_one", collection.update_one(filter, doc! { "$set": { field: rewrapped } }, None)).await {
+            Ok(result) => counts.reencrypted += result.modified_count,
+            Err(error) => {
+                warn!("failed to re-encrypt key of {} in {}: {}", id, collection.name(), error);
+                counts.failed += 1;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The data key at a dotted `field` path of a document.
+fn wrapped_key(document: &Document, field: &str) -> Option<WrappedKey> {
+    let mut path = field.split('.');
+    let mut value = document.get(path.next()?)?;
+    for name in path {
+        value = value.as_document()?.get(name)?;
+    }
+    bson::from_bson(value.clone()).ok()
+}
@@ -0,0 +1,162 @@
+//! Bulk import of texts from a newline-delimited JSON body.
+//!
+//! Every line holds one message as accepted by `POST /texts`, optionally with
+//! the `id` to store it under as written by the export. Valid lines are
+//! inserted in unordered batches so that a bad line only fails itself, and one
+//! result line is streamed back per input line.
+
+use std::collections::{HashMap, HashSet};
+
+use rocket::data::{ByteUnit, Data, Limits, ToByteUnit};
+use rocket::futures::TryStreamExt;
+use rocket::http::ContentType;
+use rocket::response::stream::TextStream;
+use rocket::serde::json::{self, json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket::tokio::io::{AsyncBufReadExt, BufReader};
+use rocket::State;
+use rocket_db_pools::mongodb::bson::{doc, Bson};
+use rocket_db_pools::mongodb::error::ErrorKind;
+use rocket_db_pools::mongodb::options::{FindOptions, InsertManyOptions};
+use rocket_db_pools::{mongodb, Connection};
+
+use crate::{build_text, discard, forget_missing, uuid_to_bson, AppConfig, Cache, Db, Message, Store, Text};
+
+const BATCH_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Line<'r> {
+    id: Option<Uuid>,
+    #[serde(flatten, borrow)]
+    msg: Message<'r>,
+}
+
+/// Used unless a `bulk` limit is configured.
+fn default_limit() -> ByteUnit {
+    1.gibibytes()
+}
+
+#[post("/texts/bulk", format = "application/x-ndjson", data = "<body>")]
+pub async fn import_texts<'r>(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    limits: &Limits,
+    body: Data<'r>,
+) -> (ContentType, TextStream![String + 'r]) {
+    let db = config.database(&mongo);
+    let config = config.inner().clone();
+    let limit = limits.get("bulk").unwrap_or_else(default_limit);
+    let results = TextStream! {
+        let mut lines = BufReader::new(body.open(limit)).lines();
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut number = 0;
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(error) => {
+                    batch.push((number + 1, Err(json!(format!("failed to read body: {}", error)))));
+                    break;
+                },
+            };
+            number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let text = match json::from_str::<Line>(&line) {
+                Err(error) => Err(json!(format!("invalid message: {}", error))),
+                Ok(Line { id, msg }) => build_text(&db, &config, id.unwrap_or_else(|| config.id_version.generate()), &msg)
+                    .await
+                    .map_err(|(_, body)| body["error"].clone()),
+            };
+            batch.push((number, text));
+            if batch.len() == BATCH_SIZE {
+                for result in insert_batch(&db, &mut cache, &config, std::mem::take(&mut batch)).await {
+                    yield result;
+                }
+            }
+        }
+        for result in insert_batch(&db, &mut cache, &config, batch).await {
+            yield result;
+        }
+    };
+    (ContentType::new("application", "x-ndjson"), results)
+}
+
+/// Inserts the valid texts of a batch and renders one result line per entry.
+async fn insert_batch(
+    db: &Db,
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    batch: Vec<(usize, Result<Text, Value>)>,
+) -> Vec<String> {
+    let texts: Vec<&Text> = batch.iter().filter_map(|(_, text)| text.as_ref().ok()).collect();
+    let mut failures = HashMap::new();
+    // set if it is unknown which texts were inserted, which are then neither reported stored nor discarded
+    let mut indeterminate = None;
+    if !texts.is_empty() {
+        let options = InsertManyOptions::builder().ordered(false).build();
+        if let Err(error) = observability::mongo("insert_many", config.texts(db).insert_many(texts.iter().copied(), options)).await {
+            match *error.kind {
+                ErrorKind::BulkWrite(ref failure) if failure.write_errors.is_some() => {
+                    for write_error in failure.write_errors.iter().flatten() {
+                        failures.insert(write_error.index, write_error.message.clone());
+                    }
+                },
+                // some texts may have been inserted before the batch failed
+                _ => match stored(db, config, &texts).await {
+                    Ok(stored) => failures.extend(
+                        (0..texts.len()).filter(|index| !stored.contains(&texts[*index]._id)).map(|index| (index, error.to_string())),
+                    ),
+                    Err(lookup) => {
+                        warn!("failed to look up which texts of a failed batch were inserted: {}", lookup);
+                        indeterminate = Some(error.to_string());
+                    },
+                },
+            }
+        }
+    }
+
+    let mut results = Vec::with_capacity(batch.len());
+    let mut inserted = Vec::with_capacity(texts.len());
+    let mut index = 0;
+    for (number, text) in batch {
+        let result = match text {
+            Err(error) => json!({ "line": number, "error": error }),
+            Ok(text) => {
+                let failure = failures.remove(&index);
+                index += 1;
+                match (failure, &indeterminate) {
+                    (None, Some(error)) => json!({
+                        "line": number,
+                        "id": text._id.as_hyphenated().to_string(),
+                        "error": format!("failed to write to DB, the text may be stored nonetheless: {}", error),
+                    }),
+                    (None, None) => {
+                        inserted.push(text._id);
+                        json!({ "line": number, "id": text._id.as_hyphenated().to_string() })
+                    },
+                    (Some(error), _) => {
+                        discard(db, &text).await;
+                        json!({ "line": number, "error": format!("failed to write to DB: {}", error) })
+                    },
+                }
+            },
+        };
+        results.push(format!("{}\n", result));
+    }
+    forget_missing(cache, config, &inserted).await;
+    results
+}
+
+/// IDs of those of `texts` that are stored, rather than other texts under the same IDs.
+async fn stored(db: &Db, config: &AppConfig, texts: &[&Text]) -> mongodb::error::Result<HashSet<Uuid>> {
+    let ids: Vec<Bson> = texts.iter().map(|text| uuid_to_bson(&text._id)).collect();
+    let options = FindOptions::builder().projection(doc! { "_id": 1, "modified_at": 1 }).build();
+    let found: Vec<Text> = observability::mongo("find", config.texts(db).find(doc! { "_id": { "$in": ids } }, options)).await?.try_collect().await?;
+    let modified_at: HashMap<Uuid, _> = texts.iter().map(|text| (text._id, text.modified_at)).collect();
+    Ok(found.into_iter().filter(|found| modified_at.get(&found._id) == Some(&found.modified_at)).map(|found| found._id).collect())
+}
@@ -0,0 +1,45 @@
+//! Compression of the data of texts at rest.
+//!
+//! Data above the configured threshold is stored as a BSON binary next to a
+//! marker naming the codec it was compressed with, in place of the `data`
+//! string. Texts stored without compression keep the plain string and are read
+//! as before.
+
+use std::io;
+
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::mongodb::bson::{self, spec::BinarySubtype};
+
+/// zstd's own default, a good trade-off for text.
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Compressed {
+    pub codec: Codec,
+    pub data: bson::Binary,
+}
+
+impl Compressed {
+    pub fn len(&self) -> usize {
+        self.data.bytes.len()
+    }
+}
+
+pub fn compress(data: &str) -> io::Result<Compressed> {
+    let bytes = zstd::encode_all(data.as_bytes(), ZSTD_LEVEL)?;
+    Ok(Compressed { codec: Codec::Zstd, data: bson::Binary { subtype: BinarySubtype::Generic, bytes } })
+}
+
+pub fn decompress(compressed: &Compressed) -> io::Result<String> {
+    let bytes = match compressed.codec {
+        Codec::Zstd => zstd::decode_all(compressed.data.bytes.as_slice())?,
+    };
+    String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
@@ -0,0 +1,395 @@
+//! Envelope encryption of text data at rest.
+//!
+//! Every text is encrypted with AES-256-GCM under a random data key of its own.
+//! That key is stored with the text, wrapped by a master key from the keyring
+//! and tagged with the master key's ID. Rotating master keys only means
+//! re-wrapping data keys, the data itself is never encrypted again. The data is
+//! bound to the ID of its text, so it cannot be moved to another text.
+//!
+//! Master keys come from a keyfile and the `ERFA_ENCRYPTION_KEYS` variable,
+//! both holding `<key id>=<base64 of 32 bytes>` entries separated by commas or
+//! newlines. Older keys stay in the keyring to read texts not re-wrapped yet.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::mongodb::bson::{self, spec::BinarySubtype};
+
+pub const KEYS_ENV: &str = "ERFA_ENCRYPTION_KEYS";
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// Plain bytes per separately sealed segment of a streamed text.
+const SEGMENT_LEN: usize = 64 * 1024;
+/// Marks Redis values sealed under a data key of their own, which plain text never starts with in practice.
+const CACHE_PREFIX: &str = "\u{1}sealed:";
+
+/// The data key of a text, wrapped by the master key `key_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WrappedKey {
+    pub key_id: String,
+    pub key: bson::Binary,
+}
+
+/// Master keys by ID, and the one new texts are encrypted under.
+#[derive(Default, Clone)]
+pub struct Keyring {
+    keys: HashMap<String, LessSafeKey>,
+    active: Option<String>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<_> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("Keyring").field("keys", &ids).field("active", &self.active).finish()
+    }
+}
+
+impl Keyring {
+    pub fn load(keyfile: Option<&Path>, env: Option<&str>, active: Option<&str>) -> Result<Keyring, String> {
+        let mut keyring = Keyring::default();
+        if let Some(keyfile) = keyfile {
+            let entries = fs::read_to_string(keyfile)
+                .map_err(|error| format!("failed to read {}: {}", keyfile.display(), error))?;
+            keyring.add_entries(&entries)?;
+        }
+        if let Some(entries) = env {
+            keyring.add_entries(entries)?;
+        }
+        if let Some(active) = active {
+            if !keyring.keys.contains_key(active) {
+                return Err(format!("encryption key {} is not in the keyring", active));
+            }
+            keyring.active = Some(active.to_owned());
+        }
+        Ok(keyring)
+    }
+
+    fn add_entries(&mut self, entries: &str) -> Result<(), String> {
+        let entries = entries.split([',', '\n']).map(str::trim).filter(|entry| !entry.is_empty() && !entry.starts_with('#'));
+        for entry in entries {
+            let (id, key) = entry.split_once('=').ok_or_else(|| "expected <key id>=<base64 key>".to_owned())?;
+            let key = BASE64.decode(key.trim()).map_err(|error| format!("key {} is not base64: {}", id, error))?;
+            if key.len() != KEY_LEN {
+                return Err(format!("key {} must be {} bytes", id, KEY_LEN));
+            }
+            self.keys.insert(id.trim().to_owned(), aes_key(&key));
+        }
+        Ok(())
+    }
+
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Creates a data key for the new text `id`, unless no master key is active.
+    pub fn generate(&self, id: &Uuid) -> io::Result<Option<(WrappedKey, DataKey)>> {
+        let Some(active) = &self.active else {
+            return Ok(None);
+        };
+        let mut key = [0; KEY_LEN];
+        SystemRandom::new().fill(&mut key).map_err(|_| io::Error::other("failed to generate a data key"))?;
+        let wrapped = WrappedKey { key_id: active.clone(), key: binary(seal(&self.keys[active], &key)?) };
+        Ok(Some((wrapped, DataKey::new(&key, id))))
+    }
+
+    /// Unwraps the data key of text `id`.
+    pub fn unwrap(&self, wrapped: &WrappedKey, id: &Uuid) -> io::Result<DataKey> {
+        Ok(DataKey::new(&self.unwrap_bytes(wrapped)?, id))
+    }
+
+    fn unwrap_bytes(&self, wrapped: &WrappedKey) -> io::Result<Vec<u8>> {
+        let master = self.keys.get(&wrapped.key_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("encryption key {} is not in the keyring", wrapped.key_id))
+        })?;
+        open(master, &wrapped.key.bytes)
+    }
+
+    /// Wraps a data key under the active master key instead.
+    pub fn rewrap(&self, wrapped: &WrappedKey) -> io::Result<WrappedKey> {
+        let active = self.active.as_ref().ok_or_else(|| io::Error::other("no encryption key is active"))?;
+        let key = self.unwrap_bytes(wrapped)?;
+        Ok(WrappedKey { key_id: active.clone(), key: binary(seal(&self.keys[active], &key)?) })
+    }
+
+    /// Seals the data of text `id` for Redis under a new data key, if a master key is active.
+    pub fn seal_cached(&self, id: &Uuid, data: &str) -> io::Result<String> {
+        let Some((wrapped, key)) = self.generate(id)? else {
+            return Ok(data.to_owned());
+        };
+        let sealed = key.seal(data.as_bytes())?;
+        Ok(format!("{}{}:{}:{}", CACHE_PREFIX, wrapped.key_id, BASE64.encode(wrapped.key.bytes), BASE64.encode(sealed)))
+    }
+
+    /// Opens the data of text `id` read from Redis, `None` if it was sealed under a key that cannot open it.
+    pub fn open_cached(&self, id: &Uuid, value: String) -> Option<String> {
+        let Some(sealed) = value.strip_prefix(CACHE_PREFIX) else {
+            return Some(value);
+        };
+        let mut parts = sealed.splitn(3, ':');
+        let (key_id, key, sealed) = (parts.next()?, parts.next()?, parts.next()?);
+        let wrapped = WrappedKey { key_id: key_id.to_owned(), key: binary(BASE64.decode(key).ok()?) };
+        let data = self.unwrap(&wrapped, id).ok()?.open(&BASE64.decode(sealed).ok()?).ok()?;
+        String::from_utf8(data).ok()
+    }
+}
+
+/// The key the data of a single text is encrypted with, bound to the text's ID.
+pub struct DataKey {
+    key: Box<LessSafeKey>,
+    id: Uuid,
+}
+
+impl DataKey {
+    fn new(key: &[u8], id: &Uuid) -> DataKey {
+        DataKey { key: Box::new(aes_key(key)), id: *id }
+    }
+
+    fn aad(&self) -> Aad<&[u8; 16]> {
+        Aad::from(self.id.as_bytes())
+    }
+
+    pub fn seal(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        seal_with(&self.key, self.aad(), data)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        open_with(&self.key, self.aad(), sealed)
+    }
+}
+
+fn aes_key(key: &[u8]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("key has the length AES-256 needs"))
+}
+
+pub fn binary(bytes: Vec<u8>) -> bson::Binary {
+    bson::Binary { subtype: BinarySubtype::Generic, bytes }
+}
+
+/// Encrypts `data` under a random nonce, which is prepended to the result.
+fn seal(key: &LessSafeKey, data: &[u8]) -> io::Result<Vec<u8>> {
+    seal_with(key, Aad::empty(), data)
+}
+
+fn seal_with<A: AsRef<[u8]>>(key: &LessSafeKey, aad: Aad<A>, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| io::Error::other("failed to generate a nonce"))?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + data.len() + TAG_LEN);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(data);
+    let tag = key
+        .seal_in_place_separate_tag(Nonce::assume_unique_for_key(nonce), aad, &mut sealed[NONCE_LEN..])
+        .map_err(|_| io::Error::other("failed to encrypt"))?;
+    sealed.extend_from_slice(tag.as_ref());
+    Ok(sealed)
+}
+
+fn open(key: &LessSafeKey, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    open_with(key, Aad::empty(), sealed)
+}
+
+fn open_with<A: AsRef<[u8]>>(key: &LessSafeKey, aad: Aad<A>, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "encrypted data is truncated"));
+    }
+    let (nonce, data) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).expect("nonce has the right length");
+    let mut data = data.to_vec();
+    let len = key
+        .open_in_place(nonce, aad, &mut data)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to decrypt"))?
+        .len();
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Nonce of a segment of a streamed text, from its index and whether it is the last one.
+///
+/// Data keys are never reused across texts, so counting segments keeps nonces unique, and
+/// marking the last segment lets a truncated stream be told from a complete one.
+fn segment_nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = u8::from(last);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypts a stream in segments that can be decrypted as they are read back.
+pub struct Sealer {
+    key: DataKey,
+    index: u64,
+    pending: Vec<u8>,
+}
+
+impl Sealer {
+    pub fn new(key: DataKey) -> Sealer {
+        Sealer { key, index: 0, pending: Vec::new() }
+    }
+
+    /// Encrypts the segments completed by `data`.
+    pub fn update(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut sealed = Vec::new();
+        // the last segment may be full too, so a segment is only sealed once more data follows it
+        while self.pending.len() > SEGMENT_LEN {
+            let rest = self.pending.split_off(SEGMENT_LEN);
+            sealed.extend(self.seal_segment(false)?);
+            self.pending = rest;
+        }
+        Ok(sealed)
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        self.seal_segment(true)
+    }
+
+    fn seal_segment(&mut self, last: bool) -> io::Result<Vec<u8>> {
+        let mut segment = std::mem::take(&mut self.pending);
+        let tag = self
+            .key
+            .key
+            .seal_in_place_separate_tag(segment_nonce(self.index, last), self.key.aad(), &mut segment)
+            .map_err(|_| io::Error::other("failed to encrypt"))?;
+        segment.extend_from_slice(tag.as_ref());
+        self.index += 1;
+        Ok(segment)
+    }
+}
+
+/// Decrypts a stream written by a [`Sealer`].
+pub struct Opener {
+    key: DataKey,
+    index: u64,
+    pending: Vec<u8>,
+}
+
+impl Opener {
+    pub fn new(key: DataKey) -> Opener {
+        Opener { key, index: 0, pending: Vec::new() }
+    }
+
+    /// Decrypts the segments completed by `data`.
+    pub fn update(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut opened = Vec::new();
+        while self.pending.len() > SEGMENT_LEN + TAG_LEN {
+            let rest = self.pending.split_off(SEGMENT_LEN + TAG_LEN);
+            opened.extend(self.open_segment(false)?);
+            self.pending = rest;
+        }
+        Ok(opened)
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        self.open_segment(true)
+    }
+
+    fn open_segment(&mut self, last: bool) -> io::Result<Vec<u8>> {
+        let mut segment = std::mem::take(&mut self.pending);
+        let len = self
+            .key
+            .key
+            .open_in_place(segment_nonce(self.index, last), self.key.aad(), &mut segment)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to decrypt"))?
+            .len();
+        segment.truncate(len);
+        self.index += 1;
+        Ok(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: Uuid = Uuid::from_u128(1);
+    const OTHER: Uuid = Uuid::from_u128(2);
+
+    fn data_key(id: &Uuid) -> DataKey {
+        DataKey::new(&[7; KEY_LEN], id)
+    }
+
+    fn keyring() -> Keyring {
+        let entries = format!("k1={}", BASE64.encode([9; KEY_LEN]));
+        Keyring::load(None, Some(&entries), Some("k1")).unwrap()
+    }
+
+    /// Seals `data` fed in pieces of `piece` bytes.
+    fn seal_stream(key: DataKey, data: &[u8], piece: usize) -> Vec<u8> {
+        let mut sealer = Sealer::new(key);
+        let mut sealed = Vec::new();
+        for chunk in data.chunks(piece) {
+            sealed.extend(sealer.update(chunk).unwrap());
+        }
+        sealed.extend(sealer.finish().unwrap());
+        sealed
+    }
+
+    fn open_stream(key: DataKey, sealed: &[u8], piece: usize) -> io::Result<Vec<u8>> {
+        let mut opener = Opener::new(key);
+        let mut opened = Vec::new();
+        for chunk in sealed.chunks(piece) {
+            opened.extend(opener.update(chunk)?);
+        }
+        opened.extend(opener.finish()?);
+        Ok(opened)
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        for len in [0, 1, SEGMENT_LEN, SEGMENT_LEN + 1, 3 * SEGMENT_LEN - 5] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let sealed = seal_stream(data_key(&TEXT), &data, 1000);
+            assert_eq!(open_stream(data_key(&TEXT), &sealed, 4096).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn stream_rejects_other_text() {
+        let sealed = seal_stream(data_key(&TEXT), b"some text", 4);
+        assert!(open_stream(data_key(&OTHER), &sealed, 4).is_err());
+    }
+
+    #[test]
+    fn stream_rejects_truncation() {
+        let data = vec![1; 2 * SEGMENT_LEN];
+        let sealed = seal_stream(data_key(&TEXT), &data, SEGMENT_LEN);
+        assert!(open_stream(data_key(&TEXT), &sealed[..SEGMENT_LEN + TAG_LEN], 4096).is_err());
+    }
+
+    #[test]
+    fn data_rejects_other_text() {
+        let sealed = data_key(&TEXT).seal(b"some text").unwrap();
+        assert_eq!(data_key(&TEXT).open(&sealed).unwrap(), b"some text");
+        assert!(data_key(&OTHER).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn wrapped_key_opens_only_its_text() {
+        let keys = keyring();
+        let (wrapped, key) = keys.generate(&TEXT).unwrap().unwrap();
+        let sealed = key.seal(b"some text").unwrap();
+        assert_eq!(keys.unwrap(&wrapped, &TEXT).unwrap().open(&sealed).unwrap(), b"some text");
+        assert!(keys.unwrap(&wrapped, &OTHER).unwrap().open(&sealed).is_err());
+    }
+
+    #[test]
+    fn cached_round_trip() {
+        let keys = keyring();
+        let sealed = keys.seal_cached(&TEXT, "some text").unwrap();
+        assert!(sealed.starts_with(CACHE_PREFIX) && !sealed.contains("some text"));
+        assert_eq!(keys.open_cached(&TEXT, sealed.clone()).as_deref(), Some("some text"));
+        assert_eq!(keys.open_cached(&OTHER, sealed), None);
+        assert_eq!(Keyring::default().seal_cached(&TEXT, "some text").unwrap(), "some text");
+    }
+}
@@ -0,0 +1,56 @@
+//! Content-addressed storage shared by all texts with identical data.
+//!
+//! Each text only keeps the hash of its data, the data itself lives once in the
+//! `contents` collection together with the number of texts referring to it.
+
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::mongodb::bson::doc;
+use rocket_db_pools::mongodb::options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions};
+use rocket_db_pools::mongodb::{self, Collection};
+use sha2::{Digest, Sha256};
+
+use crate::Db;
+
+pub const COLLECTION: &str = "contents";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Content {
+    _id: String,
+    data: String,
+    refs: i64,
+}
+
+fn contents(db: &Db) -> Collection<Content> {
+    db.collection::<Content>(&db.collection_name(COLLECTION))
+}
+
+pub fn hash(data: &str) -> String {
+    format!("{:x}", Sha256::digest(data.as_bytes()))
+}
+
+/// Stores `data` unless it is already known and takes a reference on it.
+pub async fn acquire(db: &Db, data: &str) -> mongodb::error::Result<String> {
+    let hash = hash(data);
+    let options = UpdateOptions::builder().upsert(true).build();
+    let update = doc! { "$inc": { "refs": 1 }, "$setOnInsert": { "data": data } };
+    observability::mongo("update_one", contents(db).update_one(doc! { "_id": &hash }, update, options)).await?;
+    Ok(hash)
+}
+
+/// Drops a reference, removing the content once no text refers to it anymore.
+pub async fn release(db: &Db, hash: &str) -> mongodb::error::Result<()> {
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let update = doc! { "$inc": { "refs": -1 } };
+    let content = observability::mongo("find_one_and_update", contents(db).find_one_and_update(doc! { "_id": hash }, update, options)).await?;
+    if matches!(content, Some(content) if content.refs <= 0) {
+        // a concurrent acquire may have taken a new reference in the meantime
+        observability::mongo("delete_one", contents(db).delete_one(doc! { "_id": hash, "refs": { "$lte": 0 } }, None)).await?;
+    }
+    Ok(())
+}
+
+pub async fn load(db: &Db, hash: &str) -> mongodb::error::Result<Option<String>> {
+    let content = observability::mongo("find_one", contents(db).find_one(doc! { "_id": hash }, None)).await?;
+    Ok(content.map(|content| content.data))
+}
@@ -0,0 +1,259 @@
+//! Unified diffs between two versions of a text.
+//!
+//! Lines are compared with Myers' algorithm after trimming the lines both
+//! versions start and end with. Past `MAX_EDITS` differing lines, the rest is
+//! shown as replaced as a whole, which keeps time and memory bounded.
+
+use std::fmt::Write;
+
+/// Lines of context around each change.
+const CONTEXT: usize = 3;
+const MAX_EDITS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+/// Renders the changes from `old` to `new` as a unified diff, empty if there are none.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edits(&old, &new);
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep(_)))
+        .map(|(index, _)| index)
+        .collect();
+    let mut diff = String::new();
+    if changes.is_empty() {
+        return diff;
+    }
+    let _ = writeln!(diff, "--- {}\n+++ {}", old_label, new_label);
+    // line numbers in both versions before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for edit in &edits {
+        positions.push((old_line, new_line));
+        match edit {
+            Edit::Keep(_) => (old_line, new_line) = (old_line + 1, new_line + 1),
+            Edit::Remove(_) => old_line += 1,
+            Edit::Add(_) => new_line += 1,
+        }
+    }
+    positions.push((old_line, new_line));
+
+    let mut next = 0;
+    while next < changes.len() {
+        let start = changes[next].saturating_sub(CONTEXT);
+        let mut last = changes[next];
+        next += 1;
+        // hunks whose context would touch are merged
+        while next < changes.len() && changes[next] - last <= 2 * CONTEXT + 1 {
+            last = changes[next];
+            next += 1;
+        }
+        let end = (last + CONTEXT + 1).min(edits.len());
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let _ = writeln!(
+            diff,
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        );
+        for edit in &edits[start..end] {
+            let (marker, line) = match edit {
+                Edit::Keep(line) => (' ', line),
+                Edit::Remove(line) => ('-', line),
+                Edit::Add(line) => ('+', line),
+            };
+            diff.push(marker);
+            diff.push_str(line);
+            if !line.ends_with('\n') {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    diff
+}
+
+/// A hunk range, which starts at the line before it if it is empty.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(old, new)| old == new).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let mut edits: Vec<Edit<'a>> = old[..prefix].iter().map(|line| Edit::Keep(line)).collect();
+    match myers(old_middle, new_middle) {
+        Some(middle) => edits.extend(middle),
+        None => {
+            edits.extend(old_middle.iter().map(|line| Edit::Remove(line)));
+            edits.extend(new_middle.iter().map(|line| Edit::Add(line)));
+        },
+    }
+    edits.extend(old[old.len() - suffix..].iter().map(|line| Edit::Keep(line)));
+    edits
+}
+
+/// The shortest edit script, or `None` if it takes more than `MAX_EDITS` edits.
+fn myers<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Edit<'a>>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    // furthest x reached on each diagonal k = x - y
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    // `furthest` after each round d, kept for diagonals -d..=d only
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=max.min(MAX_EDITS as isize) {
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && furthest[(offset + k - 1) as usize] < furthest[(offset + k + 1) as usize]);
+            let mut x = if down {
+                furthest[(offset + k + 1) as usize]
+            } else {
+                furthest[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                return Some(backtrack(old, new, &trace));
+            }
+        }
+        trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+    None
+}
+
+fn backtrack<'a>(old: &[&'a str], new: &[&'a str], trace: &[Vec<isize>]) -> Vec<Edit<'a>> {
+    let (mut x, mut y) = (old.len() as isize, new.len() as isize);
+    let mut edits = Vec::new();
+    for d in (1..trace.len() as isize).rev() {
+        let previous = &trace[d as usize - 1];
+        let reached = |k: isize| previous[(k + d - 1) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && reached(k - 1) < reached(k + 1));
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = reached(previous_k);
+        let previous_y = previous_x - previous_k;
+        let (edit_x, edit_y) = if down { (previous_x, previous_y + 1) } else { (previous_x + 1, previous_y) };
+        while x > edit_x && y > edit_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(old[x as usize]));
+        }
+        if down {
+            edits.push(Edit::Add(new[previous_y as usize]));
+        } else {
+            edits.push(Edit::Remove(old[previous_x as usize]));
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        edits.push(Edit::Keep(old[x as usize]));
+    }
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `edits` turn `old` into `new`.
+    fn assert_applies(old: &str, new: &str) {
+        let old: Vec<&str> = old.split_inclusive('\n').collect();
+        let new: Vec<&str> = new.split_inclusive('\n').collect();
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        for edit in edits(&old, &new) {
+            match edit {
+                Edit::Keep(line) => {
+                    before.push(line);
+                    after.push(line);
+                },
+                Edit::Remove(line) => before.push(line),
+                Edit::Add(line) => after.push(line),
+            }
+        }
+        assert_eq!(before, old);
+        assert_eq!(after, new);
+    }
+
+    #[test]
+    fn no_changes() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", "v1", "v2"), "");
+        assert_eq!(unified("", "", "v1", "v2"), "");
+    }
+
+    #[test]
+    fn changed_line() {
+        let diff = unified("a\nb\nc\n", "a\nx\nc\n", "v1", "v2");
+        assert_eq!(diff, "--- v1\n+++ v2\n@@ -1,3 +1,3 @@\n a\n-b\n+x\n c\n");
+    }
+
+    #[test]
+    fn added_to_empty() {
+        assert_eq!(unified("", "a\n", "v1", "v2"), "--- v1\n+++ v2\n@@ -0,0 +1,1 @@\n+a\n");
+    }
+
+    #[test]
+    fn missing_newline() {
+        let diff = unified("a\nb", "a\nb\n", "v1", "v2");
+        assert_eq!(diff, "--- v1\n+++ v2\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n");
+    }
+
+    #[test]
+    fn distant_changes_in_separate_hunks() {
+        let old: String = (0..20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (0..20).map(|i| if i == 2 || i == 17 { format!("{} changed\n", i) } else { format!("{}\n", i) }).collect();
+        let diff = unified(&old, &new, "v1", "v2");
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        assert!(diff.contains("@@ -1,6 +1,6 @@\n"));
+        assert!(diff.contains("@@ -15,6 +15,6 @@\n"));
+    }
+
+    #[test]
+    fn close_changes_in_one_hunk() {
+        let old: String = (0..20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (0..20).map(|i| if i == 2 || i == 9 { format!("{} changed\n", i) } else { format!("{}\n", i) }).collect();
+        assert_eq!(unified(&old, &new, "v1", "v2").matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn edits_turn_old_into_new() {
+        assert_applies("a\nb\nc\nd\n", "b\nc\ne\nd\nf\n");
+        assert_applies("x\ny\n", "");
+        assert_applies("", "x\ny\n");
+        assert_applies("a\nb\na\nb\n", "b\na\nb\na\n");
+    }
+
+    #[test]
+    fn too_many_edits_replace_the_middle() {
+        let old: String = (0..MAX_EDITS).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..MAX_EDITS).map(|i| format!("new {}\n", i)).collect();
+        let (old_lines, new_lines): (Vec<&str>, Vec<&str>) =
+            (old.split_inclusive('\n').collect(), new.split_inclusive('\n').collect());
+        assert!(myers(&old_lines, &new_lines).is_none());
+        assert_applies(&format!("same\n{}same\n", old), &format!("same\n{}same\n", new));
+    }
+}
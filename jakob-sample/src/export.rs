@@ -0,0 +1,170 @@
+//! Export of all stored texts as newline-delimited JSON.
+//!
+//! Texts are streamed straight from a cursor ordered by ID, so the output can
+//! be fed back into `POST /texts/bulk` and an interrupted export can be resumed
+//! with `after` set to the last exported ID.
+
+use std::io;
+use std::pin::Pin;
+
+use async_compression::tokio::bufread::GzipEncoder;
+use bytes::Bytes;
+use rocket::futures::stream::{self, BoxStream};
+use rocket::futures::{future, StreamExt, TryStreamExt};
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::io::AsyncRead;
+use rocket_db_pools::mongodb::bson::doc;
+use rocket_db_pools::mongodb::options::FindOptions;
+use rocket_db_pools::mongodb;
+use rocket::State;
+use rocket_db_pools::Connection;
+use tokio_util::io::StreamReader;
+
+use crate::admin::Admin;
+use crate::crypto::Keyring;
+use crate::{gridfs, not_expired, resolve, uuid_to_bson, AppConfig, Db, Store, Text};
+
+/// Whether the client accepts a gzip encoded response.
+pub struct AcceptsGzip(bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptsGzip {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let gzip = req
+            .headers()
+            .get("Accept-Encoding")
+            .flat_map(|value| value.split(','))
+            .any(|coding| coding.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("gzip"));
+        request::Outcome::Success(AcceptsGzip(gzip))
+    }
+}
+
+pub struct Export {
+    gzip: bool,
+    body: Pin<Box<dyn AsyncRead + Send>>,
+}
+
+impl<'r> Responder<'r, 'static> for Export {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.header(ContentType::new("application", "x-ndjson"));
+        if self.gzip {
+            response.raw_header("Content-Encoding", "gzip");
+        }
+        response.streamed_body(self.body).ok()
+    }
+}
+
+/// Streams decrypted texts to an admin, optionally only those after a given ID or with(out) an expiry.
+#[get("/texts/export?<after>&<expiring>&<limit>")]
+pub async fn export_texts(
+    _admin: Admin,
+    mongo: Connection<Store>,
+    config: &State<AppConfig>,
+    gzip: AcceptsGzip,
+    after: Option<Uuid>,
+    expiring: Option<bool>,
+    limit: Option<u32>,
+) -> Result<Export, (Status, Value)> {
+    let db = config.database(&mongo);
+    let mut filter = doc! { "expires_at": not_expired() };
+    if let Some(after) = after {
+        filter.insert("_id", doc! { "$gt": uuid_to_bson(&after) });
+    }
+    match expiring {
+        Some(true) => filter.insert("expires_at", doc! { "$gt": mongodb::bson::DateTime::now() }),
+        Some(false) => filter.insert("expires_at", doc! { "$exists": false }),
+        None => None,
+    };
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).limit(limit.map(i64::from)).build();
+    let cursor = match observability::mongo("find", config.texts(&db).find(filter, options)).await {
+        Ok(cursor) => cursor,
+        Err(error) => return Err((Status::InternalServerError, json!({
+            "error": format!("failed to read from DB: {}", error)
+        }))),
+    };
+    let keys = config.keys.clone();
+    let lines = cursor
+        .then(move |text| {
+            let (db, keys) = (db.clone(), keys.clone());
+            async move { render(&db, &keys, text).await }
+        })
+        .try_flatten();
+    let reader = StreamReader::new(lines);
+    let body: Pin<Box<dyn AsyncRead + Send>> = if gzip.0 {
+        Box::pin(GzipEncoder::new(reader))
+    } else {
+        Box::pin(reader)
+    };
+    Ok(Export { gzip: gzip.0, body })
+}
+
+/// Renders a text as one line, streaming the data of texts stored in GridFS.
+async fn render(
+    db: &Db,
+    keys: &Keyring,
+    text: mongodb::error::Result<Text>,
+) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    let text = resolve(db, keys, text.map_err(io::Error::other)?).await.map_err(io::Error::other)?;
+    let mut line = json!({ "id": text._id.as_hyphenated().to_string() });
+    if let Some(expires_at) = text.expires_at {
+        line["expires_at"] = json!(expires_at.try_to_rfc3339_string().map_err(io::Error::other)?);
+    }
+    if let Some(hash) = text.content {
+        line["content"] = json!(hash);
+    }
+    let Some(file) = text.file else {
+        line["data"] = json!(text.data);
+        return Ok(stream::once(future::ready(Ok(Bytes::from(format!("{}\n", line))))).boxed());
+    };
+    let key = text.key.map(|key| keys.unwrap(&key, &text._id)).transpose()?;
+    let object = gridfs::json_object(db, &line, file, key).await?;
+    Ok(object.chain(stream::once(future::ready(Ok(Bytes::from_static(b"\n"))))).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::Figment;
+    use rocket::http::{Header, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket_db_pools::Database;
+
+    use crate::{admin, AppConfig, Store};
+
+    fn figment() -> Figment {
+        rocket::Config::figment()
+            .merge(("admin_token", "secret"))
+            .merge(("databases.mongo.url", "mongodb://127.0.0.1:27017"))
+    }
+
+    async fn client() -> Client {
+        let figment = figment();
+        let config: AppConfig = figment.extract().expect("valid app config");
+        let rocket = rocket::custom(figment)
+            .attach(Store::init())
+            .manage(config)
+            .register("/", catchers![admin::unauthorized, admin::forbidden])
+            .mount("/", routes![super::export_texts]);
+        Client::untracked(rocket).await.expect("valid rocket")
+    }
+
+    #[rocket::async_test]
+    async fn export_requires_credentials() {
+        let client = client().await;
+        let response = client.get("/texts/export").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn export_rejects_wrong_token() {
+        let client = client().await;
+        let response = client.get("/texts/export").header(Header::new("Authorization", "Bearer wrong")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
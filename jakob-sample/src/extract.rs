@@ -0,0 +1,139 @@
+//! Conversion of uploaded documents into plain searchable text.
+//!
+//! Textual formats are converted to UTF-8 first, as `text-charset` does for the
+//! axum server too.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use encoding_rs::Encoding;
+use pulldown_cmark::{Event, Parser, TagEnd};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::{json, Value};
+use rocket::tokio::task;
+
+/// Line width of text rendered from HTML.
+const HTML_WIDTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Html,
+    Markdown,
+    Pdf,
+}
+
+impl Format {
+    /// Picks the format from the content type, or the file extension for generic binary uploads.
+    pub fn of(content_type: Option<&ContentType>, file_name: Option<&str>) -> Result<Format, DecodeError> {
+        let by_extension = file_name
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|extension| ContentType::from_extension(&extension.to_string_lossy()));
+        let content_type = match content_type {
+            None => by_extension.unwrap_or(ContentType::Plain),
+            Some(content_type) if content_type.is_binary() => by_extension.unwrap_or(ContentType::Plain),
+            Some(content_type) => content_type.clone(),
+        };
+        if content_type.is_plain() {
+            Ok(Format::Plain)
+        } else if content_type.is_html() {
+            Ok(Format::Html)
+        } else if content_type.is_markdown() {
+            Ok(Format::Markdown)
+        } else if content_type.is_pdf() {
+            Ok(Format::Pdf)
+        } else {
+            Err(DecodeError::UnsupportedType(content_type.to_string()))
+        }
+    }
+
+    /// Content type to keep an original under when it was uploaded without a specific one.
+    pub fn content_type(self) -> ContentType {
+        match self {
+            Format::Plain => ContentType::new("text", "plain"),
+            Format::Html => ContentType::new("text", "html"),
+            Format::Markdown => ContentType::new("text", "markdown"),
+            Format::Pdf => ContentType::PDF,
+        }
+    }
+}
+
+pub enum DecodeError {
+    UnsupportedType(String),
+    UnknownCharset(String),
+    Malformed(&'static Encoding),
+    Unreadable(String),
+}
+
+impl From<text_charset::Error> for DecodeError {
+    fn from(error: text_charset::Error) -> Self {
+        match error {
+            text_charset::Error::UnknownCharset(charset) => DecodeError::UnknownCharset(charset),
+            text_charset::Error::Malformed(encoding) => DecodeError::Malformed(encoding),
+        }
+    }
+}
+
+impl From<DecodeError> for (Status, Value) {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::UnsupportedType(content_type) => (Status::UnsupportedMediaType, json!({
+                "error": format!("unsupported content type: {}", content_type)
+            })),
+            DecodeError::UnknownCharset(charset) => (Status::UnsupportedMediaType, json!({
+                "error": format!("unsupported charset: {}", charset)
+            })),
+            DecodeError::Malformed(encoding) => (Status::UnprocessableEntity, json!({
+                "error": format!("text is not valid {}", encoding.name())
+            })),
+            DecodeError::Unreadable(error) => (Status::UnprocessableEntity, json!({
+                "error": format!("failed to extract text: {}", error)
+            })),
+        }
+    }
+}
+
+/// Extracts the plain text of a document.
+pub async fn extract(format: Format, bytes: &[u8], charset: Option<&str>) -> Result<String, DecodeError> {
+    match format {
+        Format::Plain => decode(bytes, charset).map(Cow::into_owned),
+        Format::Html => Ok(html2text::from_read(decode(bytes, charset)?.as_bytes(), HTML_WIDTH)),
+        Format::Markdown => Ok(markdown_text(&decode(bytes, charset)?)),
+        Format::Pdf => {
+            let bytes = bytes.to_vec();
+            // parsing is CPU bound and the parser panics on some malformed documents
+            match task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes)).await {
+                Ok(Ok(text)) => Ok(text),
+                Ok(Err(error)) => Err(DecodeError::Unreadable(error.to_string())),
+                Err(_) => Err(DecodeError::Unreadable("malformed PDF".to_owned())),
+            }
+        },
+    }
+}
+
+/// Converts `bytes` to UTF-8, borrowing them if they already are.
+pub fn decode<'b>(bytes: &'b [u8], charset: Option<&str>) -> Result<Cow<'b, str>, DecodeError> {
+    Ok(text_charset::decode(bytes, charset)?)
+}
+
+/// Picks the encoding of a text starting with `bytes`, along with the length of its byte order mark.
+pub fn encoding(bytes: &[u8], charset: Option<&str>) -> Result<(&'static Encoding, usize), DecodeError> {
+    Ok(text_charset::encoding(bytes, charset)?)
+}
+
+/// Keeps the text of a Markdown document, one line per block.
+fn markdown_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(chunk) | Event::Code(chunk) => text.push_str(&chunk),
+            Event::SoftBreak | Event::HardBreak | Event::Rule => text.push('\n'),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::CodeBlock | TagEnd::TableRow) => {
+                text.push('\n')
+            },
+            Event::End(TagEnd::TableCell) => text.push(' '),
+            _ => {},
+        }
+    }
+    text
+}
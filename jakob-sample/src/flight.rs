@@ -0,0 +1,76 @@
+//! Coalesces concurrent loads of the same text.
+//!
+//! When a text read often drops out of the caches, every request for it
+//! misses at once. Only the first of them loads it from the DB, the others
+//! wait for its result. Should that request be cancelled, one of the waiting
+//! ones loads the text instead.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::sync::OnceCell;
+
+#[derive(Debug)]
+pub struct Flights<T> {
+    pending: Mutex<HashMap<Uuid, Arc<OnceCell<T>>>>,
+    /// Microseconds the last load took, to estimate how early to refresh texts.
+    last_load: AtomicU64,
+}
+
+impl<T> Default for Flights<T> {
+    fn default() -> Self {
+        Flights { pending: Mutex::default(), last_load: AtomicU64::default() }
+    }
+}
+
+impl<T: Clone> Flights<T> {
+    /// Runs `load` unless a load of the same text is in flight already, whose result is returned then.
+    pub async fn load<F, Fut>(&self, uuid: Uuid, load: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let cell = self.pending.lock().unwrap().entry(uuid).or_default().clone();
+        let flight = Flight { flights: self, uuid, cell };
+        flight
+            .cell
+            .get_or_init(|| async {
+                let start = Instant::now();
+                let value = load().await;
+                self.last_load.store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                value
+            })
+            .await
+            .clone()
+    }
+
+    /// How long loading a text took recently.
+    pub fn load_time(&self) -> Duration {
+        Duration::from_micros(self.last_load.load(Ordering::Relaxed))
+    }
+}
+
+/// A request taking part in a load, which ends the flight once the text is loaded.
+struct Flight<'a, T> {
+    flights: &'a Flights<T>,
+    uuid: Uuid,
+    cell: Arc<OnceCell<T>>,
+}
+
+impl<T> Drop for Flight<'_, T> {
+    fn drop(&mut self) {
+        let mut pending = self.flights.pending.lock().unwrap();
+        let Some(current) = pending.get(&self.uuid).filter(|current| Arc::ptr_eq(current, &self.cell)) else {
+            return;
+        };
+        // a loaded text is read from the caches again from now on, and a cancelled
+        // load is only left to requests still waiting for it
+        if current.initialized() || Arc::strong_count(current) == 2 {
+            pending.remove(&self.uuid);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Texts too large for a single document, stored as chunks in a GridFS bucket.
+//!
+//! Such texts are never held in memory as a whole: uploads are decoded and
+//! written chunk by chunk, reads are streamed to the client as a JSON string
+//! and searches look at one chunk at a time. Encrypted texts are sealed in
+//! segments, see [`crypto::Sealer`].
+
+use std::io;
+use std::str;
+
+use bytes::Bytes;
+use encoding_rs::{DecoderResult, Encoding};
+use rocket::futures::stream::{self, BoxStream};
+use rocket::futures::{AsyncWriteExt, Stream, StreamExt, TryStreamExt};
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use rocket_db_pools::mongodb::bson::{self, doc, oid::ObjectId, Bson};
+use rocket_db_pools::mongodb::options::{GridFsBucketOptions, GridFsUploadOptions};
+use rocket_db_pools::mongodb::{self, GridFsBucket, GridFsUploadStream};
+use sha2::{Digest, Sha256};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tokio_util::io::ReaderStream;
+
+use crate::crypto::{DataKey, Opener, Sealer};
+use crate::extract::DecodeError;
+use crate::Db;
+
+const BUCKET: &str = "large_texts";
+/// Bytes of an upload decoded and written at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn bucket(db: &Db) -> GridFsBucket {
+    db.gridfs_bucket(GridFsBucketOptions::builder().bucket_name(db.collection_name(BUCKET)).build())
+}
+
+/// Keeps the expiry of the text with its file, so that [`sweep`] can remove it.
+fn upload_options(expires_at: Option<bson::DateTime>) -> GridFsUploadOptions {
+    let metadata = expires_at.map(|expires_at| doc! { "expires_at": expires_at });
+    GridFsUploadOptions::builder().metadata(metadata).build()
+}
+
+/// A text written to GridFS.
+pub struct Stored {
+    pub file: ObjectId,
+    /// SHA-256 of the UTF-8 data, in hex.
+    pub digest: String,
+}
+
+/// Writes the data of text `id` to a new file.
+pub async fn upload(
+    db: &Db,
+    id: Uuid,
+    data: &str,
+    key: Option<DataKey>,
+    expires_at: Option<bson::DateTime>,
+) -> mongodb::error::Result<ObjectId> {
+    let sealed = match key {
+        None => None,
+        Some(key) => {
+            let mut sealer = Sealer::new(key);
+            let mut sealed = sealer.update(data.as_bytes())?;
+            sealed.extend(sealer.finish()?);
+            Some(sealed)
+        },
+    };
+    let bytes = sealed.as_deref().unwrap_or(data.as_bytes());
+    bucket(db)
+        .upload_from_futures_0_3_reader(id.as_hyphenated().to_string(), bytes, upload_options(expires_at))
+        .await
+}
+
+/// Decodes `reader` to UTF-8 and writes it to a new file for text `id` as it is read.
+pub async fn upload_stream<R: AsyncRead + Unpin>(
+    db: &Db,
+    id: Uuid,
+    encoding: &'static Encoding,
+    reader: R,
+    max_len: u64,
+    key: Option<DataKey>,
+    expires_at: Option<bson::DateTime>,
+) -> Result<Stored, (Status, Value)> {
+    let mut upload = bucket(db).open_upload_stream(id.as_hyphenated().to_string(), upload_options(expires_at));
+    let digest = match write_decoded(&mut upload, encoding, reader, max_len, key.map(Sealer::new)).await {
+        Ok(digest) => digest,
+        Err(error) => {
+            let _ = upload.abort().await;
+            return Err(error);
+        },
+    };
+    if let Err(error) = upload.close().await {
+        return Err((Status::InternalServerError, json!({
+            "error": format!("failed to write to DB: {}", error)
+        })));
+    }
+    let file = upload.id().as_object_id().expect("upload streams are opened with an ObjectId");
+    Ok(Stored { file, digest })
+}
+
+/// Writes the data of `file`, stored in plain, to a new file for text `id` sealed under `key`.
+pub async fn encrypt(
+    db: &Db,
+    id: Uuid,
+    file: ObjectId,
+    key: DataKey,
+    expires_at: Option<bson::DateTime>,
+) -> mongodb::error::Result<ObjectId> {
+    let chunks = chunks(db, file, None).await?;
+    let mut upload = bucket(db).open_upload_stream(id.as_hyphenated().to_string(), upload_options(expires_at));
+    if let Err(error) = write_sealed(&mut upload, chunks, Sealer::new(key)).await {
+        let _ = upload.abort().await;
+        return Err(error.into());
+    }
+    upload.close().await?;
+    Ok(upload.id().as_object_id().expect("upload streams are opened with an ObjectId"))
+}
+
+async fn write_sealed(
+    upload: &mut GridFsUploadStream,
+    mut chunks: BoxStream<'static, io::Result<Bytes>>,
+    mut sealer: Sealer,
+) -> io::Result<()> {
+    while let Some(chunk) = chunks.try_next().await? {
+        upload.write_all(&sealer.update(&chunk)?).await?;
+    }
+    upload.write_all(&sealer.finish()?).await
+}
+
+async fn write_decoded<R: AsyncRead + Unpin>(
+    upload: &mut GridFsUploadStream,
+    encoding: &'static Encoding,
+    mut reader: R,
+    max_len: u64,
+    mut sealer: Option<Sealer>,
+) -> Result<String, (Status, Value)> {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut decoded = String::new();
+    let mut total = 0;
+    loop {
+        let read = reader.read(&mut buffer).await.map_err(|error| (Status::BadRequest, json!({
+            "error": format!("failed to read body: {}", error)
+        })))?;
+        total += read as u64;
+        if total > max_len {
+            return Err((Status::PayloadTooLarge, json!({
+                "error": format!("text exceeds the limit of {} bytes", max_len)
+            })));
+        }
+        let last = read == 0;
+        decoded.clear();
+        decoded.reserve(decoder.max_utf8_buffer_length_without_replacement(read).expect("chunks are small"));
+        match decoder.decode_to_string_without_replacement(&buffer[..read], &mut decoded, last).0 {
+            DecoderResult::InputEmpty => {},
+            DecoderResult::Malformed(..) => return Err(DecodeError::Malformed(encoding).into()),
+            DecoderResult::OutputFull => unreachable!("output was reserved for the whole chunk"),
+        }
+        hasher.update(decoded.as_bytes());
+        let sealed = match &mut sealer {
+            None => None,
+            Some(sealer) => Some(sealer.update(decoded.as_bytes()).map_err(encryption_failed)?),
+        };
+        write(upload, sealed.as_deref().unwrap_or(decoded.as_bytes())).await?;
+        if last {
+            if let Some(sealer) = sealer {
+                write(upload, &sealer.finish().map_err(encryption_failed)?).await?;
+            }
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+    }
+}
+
+async fn write(upload: &mut GridFsUploadStream, bytes: &[u8]) -> Result<(), (Status, Value)> {
+    upload.write_all(bytes).await.map_err(|error| (Status::InternalServerError, json!({
+        "error": format!("failed to write to DB: {}", error)
+    })))
+}
+
+fn encryption_failed(error: io::Error) -> (Status, Value) {
+    (Status::InternalServerError, json!({ "error": format!("failed to encrypt text: {}", error) }))
+}
+
+pub async fn delete(db: &Db, file: ObjectId) -> mongodb::error::Result<()> {
+    bucket(db).delete(Bson::ObjectId(file)).await
+}
+
+/// Deletes the files of texts that have expired, as the TTL monitor only removes the texts themselves.
+pub async fn sweep(db: &Db) -> mongodb::error::Result<usize> {
+    let bucket = bucket(db);
+    let mut expired = bucket.find(doc! { "metadata.expires_at": { "$lte": bson::DateTime::now() } }, None).await?;
+    let mut deleted = 0;
+    while let Some(file) = expired.try_next().await? {
+        bucket.delete(file.id).await?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+/// Streams the data of a file, decrypting it with the data key of its text if there is one.
+async fn chunks(db: &Db, file: ObjectId, key: Option<DataKey>) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    let download = bucket(db).open_download_stream(Bson::ObjectId(file)).await.map_err(io::Error::other)?;
+    let chunks = ReaderStream::new(download.compat());
+    let Some(key) = key else {
+        return Ok(chunks.boxed());
+    };
+    let opened = stream::unfold((chunks, Some(Opener::new(key))), |(mut chunks, opener)| async move {
+        let mut opener = opener?;
+        match chunks.next().await {
+            Some(Ok(chunk)) => {
+                let opened = opener.update(&chunk).map(Bytes::from);
+                Some((opened, (chunks, Some(opener))))
+            },
+            Some(Err(error)) => Some((Err(error), (chunks, None))),
+            None => Some((opener.finish().map(Bytes::from), (chunks, None))),
+        }
+    });
+    Ok(opened.boxed())
+}
+
+/// Streams the data of a file as a quoted JSON string.
+async fn json_string(
+    db: &Db,
+    file: ObjectId,
+    key: Option<DataKey>,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+    let quote = || stream::once(async { Ok(Bytes::from_static(b"\"")) });
+    let escaped = chunks(db, file, key).await?.map_ok(|chunk| escape(&chunk));
+    Ok(quote().chain(escaped).chain(quote()))
+}
+
+/// Streams the object `fields` with the data of a file added as its `data` member.
+pub async fn json_object(
+    db: &Db,
+    fields: &Value,
+    file: ObjectId,
+    key: Option<DataKey>,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+    let mut open = fields.to_string();
+    // reopen the object to append the data as its last member
+    open.pop();
+    if open.len() > 1 {
+        open.push(',');
+    }
+    open.push_str("\"data\":");
+    let data = json_string(db, file, key).await?;
+    let close = stream::once(async { Ok(Bytes::from_static(b"}")) });
+    Ok(stream::once(async { Ok(Bytes::from(open)) }).chain(data).chain(close))
+}
+
+/// Escapes UTF-8 for use inside a JSON string.
+///
+/// Bytes of multi-byte sequences never need escaping, so chunks split anywhere can be escaped on their own.
+fn escape(chunk: &[u8]) -> Bytes {
+    let mut escaped = Vec::with_capacity(chunk.len());
+    for &byte in chunk {
+        match byte {
+            b'"' => escaped.extend_from_slice(b"\\\""),
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            b'\t' => escaped.extend_from_slice(b"\\t"),
+            0..=0x1f => escaped.extend_from_slice(format!("\\u{:04x}", byte).as_bytes()),
+            _ => escaped.push(byte),
+        }
+    }
+    Bytes::from(escaped)
+}
+
+/// Whether `term` is one of the whitespace separated words of a file.
+pub async fn contains_word(db: &Db, file: ObjectId, key: Option<DataKey>, term: &str) -> io::Result<bool> {
+    if term.is_empty() || term.contains(char::is_whitespace) {
+        return Ok(false);
+    }
+    chunks_contain_word(chunks(db, file, key).await?, term).await
+}
+
+/// Whether `term`, a single word, is one of the whitespace separated words of `chunks`.
+///
+/// Only the current chunk and the word it ends in are kept, words longer than
+/// the term are skipped without being buffered.
+async fn chunks_contain_word<S: Stream<Item = io::Result<Bytes>> + Unpin>(mut chunks: S, term: &str) -> io::Result<bool> {
+    let mut pending = Vec::new();
+    // set while the start of the current word was dropped
+    let mut skipping = false;
+    while let Some(chunk) = chunks.next().await {
+        pending.extend_from_slice(&chunk?);
+        let valid = valid_prefix(&pending);
+        let Some(end) = valid.rfind(char::is_whitespace) else {
+            if skipping || valid.len() > term.len() {
+                skipping = true;
+                let len = valid.len();
+                pending.drain(..len);
+            }
+            continue;
+        };
+        let mut words = &valid[..end];
+        if skipping {
+            words = words.trim_start_matches(|c: char| !c.is_whitespace());
+            skipping = false;
+        }
+        if words.split_whitespace().any(|word| word == term) {
+            return Ok(true);
+        }
+        let separator = valid[end..].chars().next().map_or(0, char::len_utf8);
+        pending.drain(..end + separator);
+    }
+    Ok(!skipping && valid_prefix(&pending) == term)
+}
+
+/// The longest prefix of `bytes` that is valid UTF-8, leaving out a character split between chunks.
+fn valid_prefix(bytes: &[u8]) -> &str {
+    match str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => str::from_utf8(&bytes[..error.valid_up_to()]).expect("prefix is valid UTF-8"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json;
+
+    use super::*;
+
+    /// `text` split into chunks of `size` bytes, regardless of character boundaries.
+    fn split(text: &str, size: usize) -> impl Stream<Item = io::Result<Bytes>> + Unpin {
+        let chunks: Vec<_> = text.as_bytes().chunks(size).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        stream::iter(chunks)
+    }
+
+    /// Whether `term` is found in `text` however the text is split into chunks.
+    async fn found(text: &str, term: &str) -> bool {
+        let whole = chunks_contain_word(split(text, text.len().max(1)), term).await.unwrap();
+        for size in 1..text.len() {
+            assert_eq!(chunks_contain_word(split(text, size), term).await.unwrap(), whole, "chunks of {} bytes", size);
+        }
+        whole
+    }
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(&escape(b"say \"hi\"\\")[..], b"say \\\"hi\\\"\\\\");
+        assert_eq!(&escape(b"a\nb\r\tc")[..], b"a\\nb\\r\\tc");
+        assert_eq!(&escape(b"\x00\x1f\x7f")[..], b"\\u0000\\u001f\x7f");
+        assert_eq!(&escape("größe €".as_bytes())[..], "größe €".as_bytes());
+    }
+
+    #[test]
+    fn escaped_chunks_are_json() {
+        let text = "line \"one\"\n\tgröße € \u{1}\\ end";
+        for size in 1..text.len() {
+            let mut string = b"\"".to_vec();
+            for chunk in text.as_bytes().chunks(size) {
+                string.extend_from_slice(&escape(chunk));
+            }
+            string.push(b'"');
+            let parsed: String = json::from_str(str::from_utf8(&string).unwrap()).unwrap();
+            assert_eq!(parsed, text);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn whole_words_only() {
+        assert!(found("the term here", "term").await);
+        assert!(found("term", "term").await);
+        assert!(found("a\tterm\n", "term").await);
+        assert!(!found("termite determined terms", "term").await);
+        assert!(!found("", "term").await);
+        assert!(!found("ter m", "term").await);
+    }
+
+    #[rocket::async_test]
+    async fn words_after_skipped_ones() {
+        assert!(found("averyveryverylongword term", "term").await);
+        assert!(found("averyveryverylongwordterm term", "term").await);
+        assert!(!found("averyveryverylongwordterm", "term").await);
+    }
+
+    #[rocket::async_test]
+    async fn multibyte_characters() {
+        assert!(found("größe maße", "maße").await);
+        assert!(found("€€ ünïcödé €", "ünïcödé").await);
+        assert!(!found("maßeinheit", "maße").await);
+        assert!(found("a\u{3000}größe\u{a0}b", "größe").await);
+    }
+}
@@ -0,0 +1,219 @@
+//! Earlier versions of texts, kept in the `versions` collection when a text is replaced.
+//!
+//! A version holds the text document as it was stored, so it keeps sharing,
+//! compression, encryption and GridFS files as they were. Only the newest
+//! `max_versions` versions of a text are kept, older ones are discarded.
+
+use rocket::futures::TryStreamExt;
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::{json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Either, State};
+use rocket_db_pools::mongodb::bson::{self, doc, oid::ObjectId};
+use rocket_db_pools::mongodb::options::{FindOneOptions, FindOptions};
+use rocket_db_pools::mongodb::{self, Collection};
+use rocket_db_pools::Connection;
+
+use crate::{
+    diff, discard, find_text, not_expired, resolve, respond_data, text_data, uuid_to_bson, AppConfig, Db, JsonStream, Store, Text,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Version {
+    _id: ObjectId,
+    text_id: Uuid,
+    pub n: i64,
+    pub replaced_at: bson::DateTime,
+    pub text: Text,
+    /// Same as the expiry of the text, so that both are swept together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<bson::DateTime>,
+}
+
+pub const COLLECTION: &str = "versions";
+
+fn versions(db: &Db) -> Collection<Version> {
+    db.collection::<Version>(&db.collection_name(COLLECTION))
+}
+
+/// Leaves out the data of versions that are only listed.
+fn without_data() -> bson::Document {
+    doc! { "text.data": 0, "text.sealed": 0, "text.compressed": 0 }
+}
+
+/// Keeps a replaced text as a version and discards versions beyond the newest `max`.
+pub async fn archive(db: &Db, text: Text, max: usize) -> mongodb::error::Result<()> {
+    let version = Version {
+        _id: ObjectId::new(),
+        text_id: text._id,
+        n: text.version.unwrap_or(1),
+        replaced_at: bson::DateTime::now(),
+        expires_at: text.expires_at,
+        text,
+    };
+    let text_id = version.text_id;
+    if let Err(error) = observability::mongo("insert_one", versions(db).insert_one(&version, None)).await {
+        discard(db, &version.text).await;
+        return Err(error);
+    }
+    let options = FindOptions::builder()
+        .sort(doc! { "n": -1 })
+        .skip(max as u64)
+        .projection(without_data())
+        .build();
+    let mut expired = observability::mongo("find", versions(db).find(doc! { "text_id": uuid_to_bson(&text_id) }, options)).await?;
+    while let Some(version) = expired.try_next().await? {
+        remove(db, version).await?;
+    }
+    Ok(())
+}
+
+/// Versions of a text, oldest first and without their data.
+pub async fn list(db: &Db, text_id: Uuid) -> mongodb::error::Result<Vec<Version>> {
+    let options = FindOptions::builder().sort(doc! { "n": 1 }).projection(without_data()).build();
+    observability::mongo("find", versions(db).find(doc! { "text_id": uuid_to_bson(&text_id) }, options)).await?.try_collect().await
+}
+
+pub async fn find(db: &Db, text_id: Uuid, n: i64) -> mongodb::error::Result<Option<Version>> {
+    observability::mongo("find_one", versions(db).find_one(doc! { "text_id": uuid_to_bson(&text_id), "n": n }, None)).await
+}
+
+/// Removes all versions of a text that is gone.
+pub async fn remove_all(db: &Db, text_id: Uuid) -> mongodb::error::Result<()> {
+    let options = FindOptions::builder().projection(without_data()).build();
+    let mut all = observability::mongo("find", versions(db).find(doc! { "text_id": uuid_to_bson(&text_id) }, options)).await?;
+    while let Some(version) = all.try_next().await? {
+        remove(db, version).await?;
+    }
+    Ok(())
+}
+
+async fn remove(db: &Db, version: Version) -> mongodb::error::Result<()> {
+    observability::mongo("delete_one", versions(db).delete_one(doc! { "_id": version._id }, None)).await?;
+    discard(db, &version.text).await;
+    Ok(())
+}
+
+/// Time and version number of the current version of a text, without its data.
+async fn current(db: &Db, config: &AppConfig, text_id: Uuid) -> mongodb::error::Result<Option<Text>> {
+    let filter = doc! { "_id": uuid_to_bson(&text_id), "expires_at": not_expired() };
+    let options = FindOneOptions::builder().projection(doc! { "version": 1, "modified_at": 1 }).build();
+    observability::mongo("find_one", config.texts(db).find_one(filter, options)).await
+}
+
+fn rfc3339(time: Option<bson::DateTime>) -> Value {
+    json!(time.and_then(|time| time.try_to_rfc3339_string().ok()))
+}
+
+fn db_error(error: mongodb::error::Error) -> (Status, Value) {
+    (Status::InternalServerError, json!({ "error": format!("failed to get DB: {}", error) }))
+}
+
+/// Lists the versions of a text, oldest first and ending with the current one.
+#[get("/texts/<uuid>/versions")]
+pub async fn list_versions(mongo: Connection<Store>, config: &State<AppConfig>, uuid: Uuid) -> (Status, Value) {
+    let db = config.database(&mongo);
+    let current = match current(&db, config, uuid).await {
+        Err(error) => return db_error(error),
+        Ok(None) => return (Status::NotFound, json!({ "error": "text not found" })),
+        Ok(Some(current)) => current,
+    };
+    let earlier = match list(&db, uuid).await {
+        Err(error) => return db_error(error),
+        Ok(earlier) => earlier,
+    };
+    let mut versions: Vec<Value> = earlier
+        .iter()
+        .map(|version| json!({
+            "n": version.n,
+            "modified_at": rfc3339(version.text.modified_at),
+            "replaced_at": rfc3339(Some(version.replaced_at)),
+        }))
+        .collect();
+    versions.push(json!({
+        "n": current.version.unwrap_or(1),
+        "modified_at": rfc3339(current.modified_at),
+        "replaced_at": null,
+    }));
+    (Status::Ok, json!({ "versions": versions }))
+}
+
+/// Loads version `n` of a text, the current one included.
+async fn load(db: &Db, config: &AppConfig, uuid: Uuid, n: i64) -> Result<(Text, Value), (Status, Value)> {
+    let current = match current(db, config, uuid).await {
+        Err(error) => return Err(db_error(error)),
+        Ok(None) => return Err((Status::NotFound, json!({ "error": "text not found" }))),
+        Ok(Some(current)) => current,
+    };
+    if current.version.unwrap_or(1) == n {
+        let text = match find_text(db, config, uuid).await {
+            Err(error) => return Err(db_error(error)),
+            Ok(None) => return Err((Status::NotFound, json!({ "error": "text not found" }))),
+            Ok(Some(text)) => text,
+        };
+        let fields = json!({ "n": n, "modified_at": rfc3339(text.modified_at), "replaced_at": null });
+        return Ok((text, fields));
+    }
+    let version = match find(db, uuid, n).await {
+        Err(error) => return Err(db_error(error)),
+        Ok(None) => return Err((Status::NotFound, json!({ "error": "version not found" }))),
+        Ok(Some(version)) => version,
+    };
+    let fields = json!({
+        "n": n,
+        "modified_at": rfc3339(version.text.modified_at),
+        "replaced_at": rfc3339(Some(version.replaced_at)),
+    });
+    match resolve(db, &config.keys, version.text).await {
+        Err(error) => Err(db_error(error)),
+        Ok(text) => Ok((text, fields)),
+    }
+}
+
+#[get("/texts/<uuid>/versions/<n>")]
+pub async fn get_version(
+    mongo: Connection<Store>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+    n: i64,
+) -> Either<(Status, Value), JsonStream> {
+    let db = config.database(&mongo);
+    let (text, fields) = match load(&db, config, uuid, n).await {
+        Err(error) => return Either::Left(error),
+        Ok(loaded) => loaded,
+    };
+    match text_data(&config.keys, text) {
+        Ok(data) => respond_data(&db, fields, data).await,
+        Err((status, error)) => Either::Left((status, json!({ "error": error }))),
+    }
+}
+
+/// Shows the changes between two versions as a unified diff, by default those of the current version.
+#[get("/texts/<uuid>/diff?<from>&<to>")]
+pub async fn diff_versions(
+    mongo: Connection<Store>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<(ContentType, String), (Status, Value)> {
+    let db = config.database(&mongo);
+    let to = match to {
+        Some(to) => to,
+        None => match current(&db, config, uuid).await {
+            Err(error) => return Err(db_error(error)),
+            Ok(None) => return Err((Status::NotFound, json!({ "error": "text not found" }))),
+            Ok(Some(current)) => current.version.unwrap_or(1),
+        },
+    };
+    let from = from.unwrap_or(to - 1);
+    let (old, _) = load(&db, config, uuid, from).await?;
+    let (new, _) = load(&db, config, uuid, to).await?;
+    let (Some(old), Some(new)) = (old.data, new.data) else {
+        return Err((Status::UnprocessableEntity, json!({ "error": "texts stored in GridFS are too large to diff" })));
+    };
+    let diff = diff::unified(&old, &new, &format!("{}@{}", uuid, from), &format!("{}@{}", uuid, to));
+    Ok((ContentType::new("text", "x-diff"), diff))
+}
@@ -0,0 +1,134 @@
+//! Replays of `POST` responses for requests carrying an `Idempotency-Key` header.
+//!
+//! The first request with a key reserves it in Redis, its response is stored
+//! under the key afterwards and served again for every retry within the window.
+
+use redis::AsyncCommands;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{self, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::deadpool_redis;
+use sha2::{Digest, Sha256};
+
+pub const HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LEN: usize = 255;
+
+/// The optional `Idempotency-Key` header of a request.
+pub struct IdempotencyKey<'r>(pub Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IdempotencyKey(req.headers().get_one(HEADER)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Record {
+    fingerprint: String,
+    /// Unset while the first request is still being processed.
+    status: Option<u16>,
+    body: Option<Value>,
+}
+
+pub enum Begin {
+    /// The key is new and now reserved for this request.
+    New,
+    /// The response of an earlier request with the same key and payload.
+    Replay(Status, Value),
+    /// The key was used for a different payload.
+    Mismatch,
+    /// An earlier request with the same key has not completed yet.
+    InProgress,
+}
+
+pub fn is_valid(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN
+}
+
+pub fn fingerprint<T: Serialize>(payload: &T) -> String {
+    let payload = json::to_string(payload).expect("payload is serializable");
+    format!("{:x}", Sha256::digest(payload.as_bytes()))
+}
+
+fn redis_key(prefix: &str, key: &str) -> String {
+    format!("{}idempotency:{}", prefix, key)
+}
+
+/// Reserves `key` for a request or looks up what an earlier request with it got.
+///
+/// A record that cannot be read is an error rather than a new key, so that retries never
+/// both go ahead while it is kept.
+pub async fn begin(
+    cache: &mut deadpool_redis::Connection,
+    prefix: &str,
+    key: &str,
+    fingerprint: &str,
+    window: usize,
+) -> redis::RedisResult<Begin> {
+    let pending = Record { fingerprint: fingerprint.to_owned(), status: None, body: None };
+    let mut reserve = redis::cmd("SET");
+    reserve
+        .arg(redis_key(prefix, key))
+        .arg(json::to_string(&pending).expect("record is serializable"))
+        .arg("NX")
+        .arg("EX")
+        .arg(window);
+    // the record may expire or be released between reserving and reading it, so try again then
+    let record = loop {
+        let reserved: Option<String> = observability::redis("SET", reserve.query_async(&mut *cache)).await?;
+        if reserved.is_some() {
+            return Ok(Begin::New);
+        }
+        let record: Option<String> = observability::redis("GET", cache.get(redis_key(prefix, key))).await?;
+        if let Some(record) = record {
+            break record;
+        }
+    };
+    let record = json::from_str::<Record>(&record).map_err(|error| {
+        redis::RedisError::from((redis::ErrorKind::TypeError, "invalid idempotency record", error.to_string()))
+    })?;
+    if record.fingerprint != fingerprint {
+        return Ok(Begin::Mismatch);
+    }
+    match (record.status.and_then(Status::from_code), record.body) {
+        (Some(status), Some(body)) => Ok(Begin::Replay(status, body)),
+        _ => Ok(Begin::InProgress),
+    }
+}
+
+/// Stores the response for `key`, or releases the key again if the request failed on our side.
+///
+/// The response is only stored while the key is still reserved, as a text written
+/// behind may have failed to be stored and released it already.
+pub async fn finish(
+    cache: &mut deadpool_redis::Connection,
+    prefix: &str,
+    key: &str,
+    fingerprint: &str,
+    window: usize,
+    status: Status,
+    body: &Value,
+) -> redis::RedisResult<()> {
+    if status.class().is_server_error() {
+        return release(cache, prefix, key).await;
+    }
+    let record = Record { fingerprint: fingerprint.to_owned(), status: Some(status.code), body: Some(body.clone()) };
+    let mut store = redis::cmd("SET");
+    store
+        .arg(redis_key(prefix, key))
+        .arg(json::to_string(&record).expect("record is serializable"))
+        .arg("XX")
+        .arg("EX")
+        .arg(window);
+    observability::redis("SET", store.query_async(&mut *cache)).await
+}
+
+/// Releases `key`, so that the next request with it is handled as a new one.
+pub async fn release(cache: &mut deadpool_redis::Connection, prefix: &str, key: &str) -> redis::RedisResult<()> {
+    observability::redis("DEL", cache.del(redis_key(prefix, key))).await
+}
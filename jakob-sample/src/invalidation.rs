@@ -0,0 +1,88 @@
+//! Keeps the in-process caches of all replicas consistent.
+//!
+//! Replacing or deleting a text publishes its ID on a Redis channel, and every
+//! replica evicts it from its [`MemoryCache`]. While a replica is not
+//! subscribed it may miss such messages, so its cache is bypassed until it
+//! is subscribed again, and flushed then.
+
+use std::time::Duration;
+
+use redis::AsyncCommands;
+use rocket::futures::StreamExt;
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::time::sleep;
+use rocket_db_pools::deadpool_redis;
+
+use crate::memory::MemoryCache;
+
+/// Longest wait between attempts to subscribe again.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Publishes evictions for this replica.
+#[derive(Debug, Clone, Default)]
+pub struct Invalidator {
+    channel: String,
+    /// Tells the messages of this replica apart, which has evicted the text itself already.
+    replica: String,
+}
+
+impl Invalidator {
+    /// Texts of one database and collection share a channel.
+    pub fn new(database: &str, collection: &str) -> Invalidator {
+        Invalidator { channel: format!("{}.{}:invalidate", database, collection), replica: Uuid::new_v4().simple().to_string() }
+    }
+
+    /// Tells the other replicas to evict a text.
+    pub async fn publish(&self, cache: &mut deadpool_redis::Connection, uuid: Uuid) {
+        let message = format!("{} {}", self.replica, uuid.as_hyphenated());
+        if let Err(error) = observability::redis("PUBLISH", cache.publish::<_, _, ()>(&self.channel, message)).await {
+            // the other replicas only notice their cache is stale once their entries expire
+            warn!("failed to publish eviction of {}: {}", uuid, error);
+        }
+    }
+
+    /// Evicts the texts other replicas publish, for as long as the server runs.
+    pub async fn subscribe(self, url: String, memory: MemoryCache) {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            match self.listen(&url, &memory).await {
+                Ok(()) => {
+                    warn!("lost subscription to {}, bypassing in-process cache", self.channel);
+                    backoff = Duration::from_secs(1);
+                },
+                Err(error) => warn!("failed to subscribe to {}: {}", self.channel, error),
+            }
+            memory.set_synced(false);
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Evicts texts until the connection is lost.
+    async fn listen(&self, url: &str, memory: &MemoryCache) -> redis::RedisResult<()> {
+        let client = redis::Client::open(url)?;
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(&self.channel).await?;
+        // evictions may have been missed while not subscribed
+        memory.invalidate_all();
+        memory.set_synced(true);
+        info!("subscribed to {}", self.channel);
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let Ok(payload) = message.get_payload::<String>() else {
+                continue;
+            };
+            let Some((replica, uuid)) = payload.split_once(' ') else {
+                continue;
+            };
+            if replica == self.replica {
+                continue;
+            }
+            match Uuid::parse_str(uuid) {
+                Ok(uuid) => memory.invalidate(&uuid).await,
+                Err(_) => warn!("ignoring eviction of invalid ID {:?}", uuid),
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,22 +3,66 @@
 #[macro_use]
 extern crate rocket;
 
+mod admin;
+mod bulk;
+mod compression;
+mod crypto;
+mod diff;
+mod dedup;
+mod export;
+mod extract;
+mod flight;
+mod gridfs;
+mod history;
+mod idempotency;
+mod invalidation;
+mod memory;
+mod original;
+mod schema;
+mod terms;
+mod upload;
+mod write;
+
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
 use once_cell::sync::Lazy;
-use rocket::http::Status;
+use rocket::Either;
+use rocket::fairing::{self, AdHoc};
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::io::AsyncRead;
 use rocket::serde::uuid::Uuid;
 use rocket::serde::json::{Json, json, Value};
 use rocket::serde::{Deserialize, Serialize};
-use rocket::Request;
+use rocket::{Build, Orbit, Request, Rocket, State};
 use rocket_db_pools::{deadpool_redis, mongodb, Connection, Database};
 use redis::AsyncCommands;
+use idempotency::{Begin, IdempotencyKey};
 use mongodb::bson;
 use mongodb::bson::doc;
-use rocket_prometheus::{
-    prometheus::{opts, IntCounterVec},
-    PrometheusMetrics,
-};
+use mongodb::options::ReplaceOptions;
+use tokio_util::io::StreamReader;
+use prometheus::{histogram_opts, Histogram};
+use observability::metrics::CACHE_COUNTER;
 
-const EXPIRE: usize = 7200;
+const EXPIRE: Duration = Duration::from_secs(7200);
+/// Remaining lifetime below which a text is not cached, as it would expire before being read again.
+const MIN_CACHE_TTL: Duration = Duration::from_millis(100);
+/// Service name of the traces unless `OTEL_SERVICE_NAME` is set.
+const SERVICE: &str = "ho-erfa-sample";
+/// Cached in place of the data of texts that do not exist, which is not UTF-8 unlike the data of any text.
+const MISSING: &[u8] = b"\xFFmissing";
+/// Same period as the TTL monitor sweeping expired texts.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Attempts at replacing a text that is replaced concurrently.
+const SWAP_ATTEMPTS: usize = 3;
 
 #[derive(Database)]
 #[database("redis")]
@@ -28,103 +72,977 @@ struct Cache(deadpool_redis::Pool);
 #[database("mongo")]
 struct Store(mongodb::Client);
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AppConfig {
+    /// Share the storage of texts with identical data.
+    #[serde(default)]
+    dedup: bool,
+    /// Seconds for which responses to requests with an `Idempotency-Key` are replayed.
+    #[serde(default = "default_idempotency_window")]
+    idempotency_window: usize,
+    /// UUID version of server-generated text IDs.
+    #[serde(default)]
+    id_version: IdVersion,
+    /// Bytes above which the data of a text is stored in GridFS rather than in its document.
+    #[serde(default = "default_gridfs_threshold")]
+    gridfs_threshold: usize,
+    /// Bytes above which the data of a text is stored compressed, unset to never compress.
+    #[serde(default)]
+    compression_threshold: Option<usize>,
+    /// File with master keys, see [`crypto`].
+    #[serde(default)]
+    encryption_keyfile: Option<PathBuf>,
+    /// ID of the master key new texts are encrypted under, unset to store them unencrypted.
+    #[serde(default)]
+    encryption_key: Option<String>,
+    /// Bearer token authorizing admin operations, unset to disable them.
+    #[serde(default)]
+    admin_token: Option<String>,
+    /// Number of earlier versions kept per text, 0 to keep none.
+    #[serde(default = "default_max_versions")]
+    max_versions: usize,
+    /// Database all collections are kept in.
+    #[serde(default = "default_mongo_database")]
+    mongo_database: String,
+    /// Collection the texts themselves are kept in.
+    #[serde(default = "default_mongo_collection")]
+    mongo_collection: String,
+    /// Bytes of texts cached in process in front of Redis, 0 to disable that cache.
+    #[serde(default = "default_memory_cache_bytes")]
+    memory_cache_bytes: u64,
+    /// Seconds texts stay cached in process at most.
+    #[serde(default = "default_memory_cache_ttl")]
+    memory_cache_ttl: u64,
+    /// When new texts are cached.
+    #[serde(default)]
+    cache_policy: write::CachePolicy,
+    /// Seconds texts that were not found are remembered as missing, 0 to always look them up.
+    #[serde(default = "default_negative_cache_ttl")]
+    negative_cache_ttl: usize,
+    /// How eagerly cached texts are reloaded before they expire, 0 to only reload them once expired.
+    #[serde(default)]
+    cache_early_refresh: f64,
+    #[serde(skip)]
+    keys: Arc<crypto::Keyring>,
+    #[serde(skip)]
+    memory: memory::MemoryCache,
+    #[serde(skip)]
+    invalidator: invalidation::Invalidator,
+    #[serde(skip)]
+    background: write::Background,
+    #[serde(skip)]
+    flights: Arc<flight::Flights<Result<Loaded, (Status, String)>>>,
+}
+
+impl AppConfig {
+    fn database(&self, mongo: &mongodb::Client) -> Db {
+        Db { db: mongo.database(&self.mongo_database), texts: self.mongo_collection.clone() }
+    }
+
+    fn texts(&self, db: &mongodb::Database) -> mongodb::Collection<Text> {
+        db.collection::<Text>(&self.mongo_collection)
+    }
+
+    /// Prefix of the Redis keys of the texts, so that those of different databases and collections stay apart.
+    fn redis_prefix(&self) -> String {
+        format!("{}.{}:", self.mongo_database, self.mongo_collection)
+    }
+
+    /// Redis key a text is cached under.
+    fn cache_key(&self, uuid: &Uuid) -> String {
+        format!("{}{}", self.redis_prefix(), uuid.as_hyphenated())
+    }
+}
+
+/// The database of the texts, which knows the collections kept for them.
+#[derive(Debug, Clone)]
+struct Db {
+    db: mongodb::Database,
+    /// Collection of the texts themselves.
+    texts: String,
+}
+
+impl Deref for Db {
+    type Target = mongodb::Database;
+
+    fn deref(&self) -> &mongodb::Database {
+        &self.db
+    }
+}
+
+impl Db {
+    /// Name of a collection kept for the texts, e.g. `texts.versions`, so that texts sharing a database stay apart.
+    ///
+    /// Collections of the default texts collection keep the plain names they had before it was configurable.
+    fn collection_name(&self, name: &str) -> String {
+        if self.texts == default_mongo_collection() {
+            return name.to_owned();
+        }
+        format!("{}.{}", self.texts, name)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum IdVersion {
+    /// Random IDs.
+    #[default]
+    V4,
+    /// Time-ordered IDs, which keep inserts into the `_id` index local.
+    V7,
+}
+
+impl IdVersion {
+    fn generate(self) -> Uuid {
+        match self {
+            IdVersion::V4 => Uuid::new_v4(),
+            IdVersion::V7 => Uuid::now_v7(),
+        }
+    }
+}
+
+fn default_idempotency_window() -> usize {
+    86400
+}
+
+fn default_max_versions() -> usize {
+    10
+}
+
+fn default_mongo_database() -> String {
+    "erfa".to_owned()
+}
+
+fn default_mongo_collection() -> String {
+    "texts".to_owned()
+}
+
+fn default_memory_cache_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_memory_cache_ttl() -> u64 {
+    60
+}
+
+fn default_negative_cache_ttl() -> usize {
+    5
+}
+
+fn default_gridfs_threshold() -> usize {
+    8 * 1024 * 1024
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct Message<'r> {
-    data: &'r str,
+    #[serde(borrow)]
+    data: Cow<'r, str>,
+    ttl_seconds: Option<u64>,
+    #[serde(borrow)]
+    expires_at: Option<Cow<'r, str>>,
+    /// The uploaded document the data was extracted from.
+    #[serde(skip)]
+    original: Option<original::Attachment>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct Text {
     _id: Uuid,
-    data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    /// Hash of the shared content holding the data, see [`dedup`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    /// GridFS file holding the data of a large text, see [`gridfs`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<bson::oid::ObjectId>,
+    /// The data of the text if it was stored compressed, see [`compression`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed: Option<compression::Compressed>,
+    /// Data key the data of the text is encrypted with, see [`crypto`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<crypto::WrappedKey>,
+    /// The encrypted data of a text that is neither compressed nor stored in GridFS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed: Option<bson::Binary>,
+    /// Number of the version, counting from 1, see [`history`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified_at: Option<bson::DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<bson::DateTime>,
 }
 
-static TERM_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
-    IntCounterVec::new(opts!("search_term_counter", "Count of times a term was searched for"), &["term"])
-        .expect("Could not create lazy IntCounterVec")
-});
-static CACHE_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
-    IntCounterVec::new(opts!("cache_counter", "Count hits and misses on cache"), &["type"])
-        .expect("Could not create lazy IntCounterVec")
+static COMPRESSION_RATIO: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        histogram_opts!("compression_ratio", "Ratio of the original to the compressed size of stored texts")
+            .buckets(vec![1.0, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 32.0]),
+    )
+    .expect("Could not create lazy Histogram")
 });
 
 #[post("/texts", format = "json", data = "<msg>")]
-async fn store_text(mongo: Connection<Store>, mut cache: Connection<Cache>, msg: Json<Message<'_>>) -> (Status, Value) {
-    let collection = mongo.database("erfa").collection::<Text>("texts");
-    let id = Uuid::new_v4();
-    let text = Text { _id: id, data: msg.data.to_owned() };
-    let _: redis::RedisResult<String> = cache.set_ex(id.to_string(), msg.data, EXPIRE).await;
-    match collection.insert_one(text, None).await {
-        Err(error) => (Status::InternalServerError, json!({
+async fn store_text(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    key: IdempotencyKey<'_>,
+    msg: Json<Message<'_>>,
+) -> (Status, Value) {
+    store_message(&mongo, &mut cache, config, key, &msg).await
+}
+
+/// Creates a text from a message of any accepted format, honouring its idempotency key.
+async fn store_message(
+    mongo: &Connection<Store>,
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    key: IdempotencyKey<'_>,
+    msg: &Message<'_>,
+) -> (Status, Value) {
+    let Some(key) = key.0 else {
+        return create_text(mongo, cache, config, None, msg).await;
+    };
+    let fingerprint = idempotency::fingerprint(msg);
+    if let Err(response) = reserve(cache, config, key, &fingerprint).await {
+        return response;
+    }
+    let (status, body) = create_text(mongo, cache, config, Some(key), msg).await;
+    record(cache, config, key, &fingerprint, status, &body).await;
+    (status, body)
+}
+
+/// Reserves an idempotency key for a request, or answers it right away if the key was used before.
+async fn reserve(
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    key: &str,
+    fingerprint: &str,
+) -> Result<(), (Status, Value)> {
+    if !idempotency::is_valid(key) {
+        return Err((Status::BadRequest, json!({
+            "error": format!("{} must be between 1 and 255 characters", idempotency::HEADER)
+        })));
+    }
+    match idempotency::begin(cache, &config.redis_prefix(), key, fingerprint, config.idempotency_window).await {
+        Err(error) => Err((Status::InternalServerError, json!({
+            "error": format!("failed to read idempotency key: {}", error)
+        }))),
+        Ok(Begin::Replay(status, body)) => Err((status, body)),
+        Ok(Begin::Mismatch) => Err((Status::UnprocessableEntity, json!({
+            "error": format!("{} was already used for a different payload", idempotency::HEADER)
+        }))),
+        Ok(Begin::InProgress) => Err((Status::Conflict, json!({
+            "error": format!("a request with this {} is still in progress", idempotency::HEADER)
+        }))),
+        Ok(Begin::New) => Ok(()),
+    }
+}
+
+/// Stores the response to a request with a reserved idempotency key.
+async fn record(
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    key: &str,
+    fingerprint: &str,
+    status: Status,
+    body: &Value,
+) {
+    if let Err(error) = idempotency::finish(cache, &config.redis_prefix(), key, fingerprint, config.idempotency_window, status, body).await {
+        warn!("failed to store response for idempotency key {}: {}", key, error);
+    }
+}
+
+/// Creates a text, releasing the reserved idempotency key should a text written behind not be stored.
+async fn create_text(
+    mongo: &Connection<Store>,
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    key: Option<&str>,
+    msg: &Message<'_>,
+) -> (Status, Value) {
+    let db = config.database(mongo);
+    let id = config.id_version.generate();
+    let text = match build_text(&db, config, id, msg).await {
+        Ok(text) => text,
+        Err(error) => return error,
+    };
+    if let Some(attachment) = &msg.original {
+        if let Err(error) = original::store(&db, &config.keys, id, attachment, text.key.as_ref(), text.expires_at).await {
+            discard(&db, &text).await;
+            return (Status::InternalServerError, json!({
+                "error": format!("failed to write original to DB: {}", error)
+            }));
+        }
+    }
+    let created = (Status::Created, json!({
+        "id": id.as_hyphenated().to_string()
+    }));
+    if text.file.is_none() && config.cache_policy == write::CachePolicy::Behind {
+        cache_data(cache, config, id, &msg.data, text.expires_at).await;
+        config.background.insert(db, config.clone(), text, key.map(str::to_owned));
+        return created;
+    }
+    if let Err(error) = observability::mongo("insert_one", config.texts(&db).insert_one(&text, None)).await {
+        discard(&db, &text).await;
+        if msg.original.is_some() {
+            let _ = original::remove(&db, id).await;
+        }
+        return (Status::InternalServerError, json!({
             "error": format!("failed to write to DB: {}", error)
-        })),
-        Ok(_) => (Status::Created, json!({
-            "id": id.as_hyphenated().to_string()
-        })),
+        }));
+    }
+    if text.file.is_none() && config.cache_policy == write::CachePolicy::Through {
+        cache_data(cache, config, id, &msg.data, text.expires_at).await;
+    }
+    created
+}
+
+#[put("/texts/<uuid>", format = "json", data = "<msg>")]
+async fn replace_text(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+    msg: Json<Message<'_>>,
+) -> (Status, Value) {
+    let db = config.database(&mongo);
+    let mut text = match build_text(&db, config, uuid, &msg).await {
+        Ok(text) => text,
+        Err(error) => return error,
+    };
+    let previous = match swap_text(&config.texts(&db), &mut text).await {
+        Ok(Some(previous)) => previous,
+        Ok(None) => {
+            discard(&db, &text).await;
+            return (Status::Conflict, json!({ "error": "text was replaced concurrently, try again" }));
+        },
+        Err(error) => {
+            discard(&db, &text).await;
+            return (Status::InternalServerError, json!({
+                "error": format!("failed to write to DB: {}", error)
+            }));
+        },
+    };
+    // replacements are always stored first, as they may conflict
+    if text.file.is_none() && config.cache_policy != write::CachePolicy::Around {
+        cache_data(&mut cache, config, uuid, &msg.data, text.expires_at).await;
+        config.invalidator.publish(&mut cache, uuid).await;
+    } else {
+        uncache(&mut cache, config, uuid).await;
+    }
+    // the new data was not extracted from the original of the replaced text
+    if let Err(error) = original::remove(&db, uuid).await {
+        warn!("failed to remove original of {}: {}", uuid, error);
+    }
+    let Some(previous) = previous else {
+        return (Status::Created, json!({ "id": uuid.as_hyphenated().to_string() }));
+    };
+    // a text that expired but was not swept yet is replaced like a missing one
+    if previous.expires_at.is_some_and(|expired| expired <= bson::DateTime::now()) {
+        discard(&db, &previous).await;
+        if let Err(error) = history::remove_all(&db, uuid).await {
+            warn!("failed to remove versions of {}: {}", uuid, error);
+        }
+        return (Status::Created, json!({ "id": uuid.as_hyphenated().to_string() }));
+    }
+    if config.max_versions == 0 {
+        discard(&db, &previous).await;
+    } else if let Err(error) = history::archive(&db, previous, config.max_versions).await {
+        warn!("failed to keep version of {}: {}", uuid, error);
+    }
+    (Status::Ok, json!({ "id": uuid.as_hyphenated().to_string() }))
+}
+
+/// Replaces the stored text with `text`, numbering it as the next version.
+///
+/// Returns the replaced text, if there was one, or `None` if the text kept being
+/// replaced concurrently. Writes only succeed if the version read is still the
+/// stored one, so that no version is lost.
+async fn swap_text(collection: &mongodb::Collection<Text>, text: &mut Text) -> mongodb::error::Result<Option<Option<Text>>> {
+    let id = uuid_to_bson(&text._id);
+    for _ in 0..SWAP_ATTEMPTS {
+        let current = observability::mongo("find_one", collection.find_one(doc! { "_id": &id }, None)).await?;
+        let live = current.as_ref().filter(|current| current.expires_at.is_none_or(|expires_at| expires_at > bson::DateTime::now()));
+        text.version = Some(live.map_or(1, |current| current.version.unwrap_or(1) + 1));
+        // null also matches texts stored before versions were numbered
+        let filter = doc! { "_id": &id, "version": current.as_ref().and_then(|current| current.version) };
+        let options = ReplaceOptions::builder().upsert(current.is_none()).build();
+        match observability::mongo("replace_one", collection.replace_one(filter, &*text, options)).await {
+            Ok(result) if result.matched_count > 0 || result.upserted_id.is_some() => return Ok(Some(current)),
+            Ok(_) => continue,
+            // another request created the text in between
+            Err(error) if is_duplicate_key(&error) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(None)
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        *error.kind,
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(ref failure)) if failure.code == 11000
+    )
+}
+
+/// Validates a message and turns it into the document stored for text `id`.
+async fn build_text(
+    db: &Db,
+    config: &AppConfig,
+    id: Uuid,
+    msg: &Message<'_>,
+) -> Result<Text, (Status, Value)> {
+    let expires_at = match expiry(msg.ttl_seconds, msg.expires_at.as_deref()) {
+        Ok(expires_at) => expires_at,
+        Err(error) => return Err((Status::UnprocessableEntity, json!({ "error": error }))),
+    };
+    let (key, data_key) = match config.keys.generate(&id) {
+        Ok(Some((key, data_key))) => (Some(key), Some(data_key)),
+        Ok(None) => (None, None),
+        Err(error) => return Err((Status::InternalServerError, json!({
+            "error": format!("failed to encrypt text: {}", error)
+        }))),
+    };
+    let mut text = Text {
+        _id: id,
+        key,
+        version: Some(1),
+        modified_at: Some(bson::DateTime::now()),
+        expires_at,
+        ..Default::default()
+    };
+    if msg.data.len() > config.gridfs_threshold {
+        return match gridfs::upload(db, id, &msg.data, data_key, expires_at).await {
+            Err(error) => Err((Status::InternalServerError, json!({
+                "error": format!("failed to write to DB: {}", error)
+            }))),
+            Ok(file) => Ok(Text { file: Some(file), ..text }),
+        };
+    }
+    // the TTL monitor does not release content, so expiring texts are always stored inline,
+    // and content is addressed by the hash of its plain data, so encrypted texts are never shared
+    if config.dedup && expires_at.is_none() && data_key.is_none() {
+        return match dedup::acquire(db, &msg.data).await {
+            Err(error) => Err((Status::InternalServerError, json!({
+                "error": format!("failed to write to DB: {}", error)
+            }))),
+            Ok(hash) => Ok(Text { content: Some(hash), ..text }),
+        };
+    }
+    let mut compressed = None;
+    if config.compression_threshold.is_some_and(|threshold| msg.data.len() > threshold) {
+        let candidate = match compression::compress(&msg.data) {
+            Ok(candidate) => candidate,
+            Err(error) => return Err((Status::InternalServerError, json!({
+                "error": format!("failed to compress text: {}", error)
+            }))),
+        };
+        COMPRESSION_RATIO.observe(msg.data.len() as f64 / candidate.len().max(1) as f64);
+        // data that does not shrink is not worth decompressing on every read
+        if candidate.len() < msg.data.len() {
+            compressed = Some(candidate);
+        }
+    }
+    let Some(data_key) = data_key else {
+        return Ok(Text { data: compressed.is_none().then(|| msg.data.to_string()), compressed, ..text });
+    };
+    let plain = match &compressed {
+        Some(compressed) => compressed.data.bytes.as_slice(),
+        None => msg.data.as_bytes(),
+    };
+    let sealed = match data_key.seal(plain) {
+        Ok(sealed) => crypto::binary(sealed),
+        Err(error) => return Err((Status::InternalServerError, json!({
+            "error": format!("failed to encrypt text: {}", error)
+        }))),
+    };
+    match &mut compressed {
+        Some(compressed) => compressed.data = sealed,
+        None => text.sealed = Some(sealed),
+    }
+    Ok(Text { compressed, ..text })
+}
+
+/// Releases the storage of a text that was not written or is gone.
+async fn discard(db: &Db, text: &Text) {
+    if let Some(hash) = &text.content {
+        if let Err(error) = dedup::release(db, hash).await {
+            warn!("failed to release content {}: {}", hash, error);
+        }
+    }
+    if let Some(file) = text.file {
+        if let Err(error) = gridfs::delete(db, file).await {
+            warn!("failed to delete file {} of {}: {}", file, text._id, error);
+        }
     }
 }
 
 #[get("/texts/<uuid>")]
-async fn get_text(mongo: Connection<Store>, cache: Connection<Cache>, uuid: Uuid) -> (Status, Value) {
-    let (status, val) = get_val(mongo, cache, uuid).await;
-    match status.code {
-        200 => (status, json!({ "data": val })),
-        _ => (status, json!({ "error": val })),
+async fn get_text(
+    mongo: Connection<Store>,
+    cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+) -> Either<(Status, Value), JsonStream> {
+    let db = config.database(&mongo);
+    match get_val(&db, cache, config, uuid).await {
+        Ok(data) => respond_data(&db, json!({}), data).await,
+        Err((status, error)) => Either::Left((status, json!({ "error": error }))),
+    }
+}
+
+/// Responds with the object `fields` and the data of a text as its `data` member.
+async fn respond_data(db: &Db, mut fields: Value, data: TextData) -> Either<(Status, Value), JsonStream> {
+    match data {
+        TextData::Inline(data) => {
+            fields["data"] = json!(data);
+            Either::Left((Status::Ok, fields))
+        },
+        TextData::Stored(file, key) => match gridfs::json_object(db, &fields, file, key).await {
+            Ok(body) => Either::Right(JsonStream(Box::pin(StreamReader::new(body)))),
+            Err(error) => Either::Left((Status::InternalServerError, json!({
+                "error": format!("failed to get DB: {}", error)
+            }))),
+        },
+    }
+}
+
+/// A JSON body streamed as it is read.
+struct JsonStream(Pin<Box<dyn AsyncRead + Send>>);
+
+impl<'r> Responder<'r, 'static> for JsonStream {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build().header(ContentType::JSON).streamed_body(self.0).ok()
     }
 }
 
 #[get("/texts/<uuid>/search?<term>")]
-async fn search_text(mongo: Connection<Store>, cache: Connection<Cache>, uuid: Uuid, term: &str) -> (Status, Value) {
-    TERM_COUNTER.with_label_values(&[term]).inc();
-    let (status, val) = get_val(mongo, cache, uuid).await;
-    match status.code {
-        200 => (status, json!({ "found": val.split_whitespace().any(|x| x == term) })),
-        _ => (status, json!({ "error": val })),
-    }
-}
-
-async fn get_val(mongo: Connection<Store>, mut cache: Connection<Cache>, uuid: Uuid) -> (Status, String) {
-    if let Ok(data) = cache.get::<String, String>(uuid.to_string()).await {
-        CACHE_COUNTER.with_label_values(&["hit"]).inc();
-        return (Status::Ok, data);
-    }
-    CACHE_COUNTER.with_label_values(&["miss"]).inc();
-    let collection = mongo.database("erfa").collection::<Text>("texts");
-    match collection.find_one(doc! { "_id": uuid_to_bson(&uuid) }, None).await {
-        Err(error) => (Status::InternalServerError, format!("failed to get DB: {}", error)),
-        Ok(Some(res)) => {
-            let _: redis::RedisResult<String> = cache.set_ex(uuid.to_string(), &res.data, EXPIRE).await;
-            (Status::Ok, res.data)
+async fn search_text(
+    mongo: Connection<Store>,
+    cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+    term: &str,
+) -> (Status, Value) {
+    terms::TERMS.record(term);
+    if let Some(found) = config.memory.contains_word(&uuid, term).await {
+        CACHE_COUNTER.with_label_values(&["memory", "hit"]).inc();
+        return (Status::Ok, json!({ "found": found }));
+    }
+    let db = config.database(&mongo);
+    match get_val(&db, cache, config, uuid).await {
+        Ok(TextData::Inline(data)) => (Status::Ok, json!({ "found": data.split_whitespace().any(|x| x == term) })),
+        Ok(TextData::Stored(file, key)) => match gridfs::contains_word(&db, file, key, term).await {
+            Ok(found) => (Status::Ok, json!({ "found": found })),
+            Err(error) => (Status::InternalServerError, json!({
+                "error": format!("failed to get DB: {}", error)
+            })),
+        },
+        Err((status, error)) => (status, json!({ "error": error })),
+    }
+}
+
+/// Where the data of a text can be read from.
+enum TextData {
+    Inline(String),
+    /// A GridFS file, too large to be read at once or cached, with the key it is encrypted with.
+    Stored(bson::oid::ObjectId, Option<crypto::DataKey>),
+}
+
+/// A text loaded from the DB, shared by the requests that loaded it together.
+#[derive(Debug, Clone)]
+enum Loaded {
+    Inline(Arc<str>),
+    Stored(bson::oid::ObjectId, Option<crypto::WrappedKey>),
+}
+
+impl Loaded {
+    fn new(text: Text) -> Result<Loaded, (Status, String)> {
+        match text {
+            Text { file: Some(file), key, .. } => Ok(Loaded::Stored(file, key)),
+            Text { data: Some(data), .. } => Ok(Loaded::Inline(Arc::from(data))),
+            Text { .. } => Err((Status::NotFound, "text not found".to_owned())),
+        }
+    }
+
+    /// Where to read the data from.
+    fn open(self, keys: &crypto::Keyring, uuid: &Uuid) -> Result<TextData, (Status, String)> {
+        match self {
+            Loaded::Stored(file, key) => match key.map(|key| keys.unwrap(&key, uuid)).transpose() {
+                Ok(key) => Ok(TextData::Stored(file, key)),
+                Err(error) => Err((Status::InternalServerError, format!("failed to decrypt text: {}", error))),
+            },
+            Loaded::Inline(data) => Ok(TextData::Inline(data.to_string())),
+        }
+    }
+}
+
+async fn get_val(
+    db: &Db,
+    mut cache: Connection<Cache>,
+    config: &AppConfig,
+    uuid: Uuid,
+) -> Result<TextData, (Status, String)> {
+    if let Some(data) = config.memory.get(&uuid).await {
+        CACHE_COUNTER.with_label_values(&["memory", "hit"]).inc();
+        return Ok(TextData::Inline(data));
+    }
+    if config.memory.is_enabled() {
+        CACHE_COUNTER.with_label_values(&["memory", "miss"]).inc();
+    }
+    let key = config.cache_key(&uuid);
+    let cached: redis::RedisResult<(Option<Vec<u8>>, i64)> =
+        observability::redis("PIPELINE", redis::pipe().get(&key).pttl(&key).query_async(&mut *cache)).await;
+    if matches!(&cached, Ok((Some(value), _)) if value == MISSING) {
+        CACHE_COUNTER.with_label_values(&["redis", "negative_hit"]).inc();
+        return Err((Status::NotFound, "text not found".to_owned()));
+    }
+    let mut outcome = "miss";
+    // data sealed under a key that was removed since is read from the DB again
+    if let Ok((Some(value), ttl)) = cached {
+        if let Some(data) = String::from_utf8(value).ok().and_then(|value| config.keys.open_cached(&uuid, value)) {
+            if !refresh_early(config, ttl) {
+                CACHE_COUNTER.with_label_values(&["redis", "hit"]).inc();
+                // never outlive the entry in Redis, which never outlives the text
+                if let Ok(ttl) = u64::try_from(ttl) {
+                    config.memory.insert(uuid, &data, Duration::from_millis(ttl)).await;
+                }
+                return Ok(TextData::Inline(data));
+            }
+            outcome = "refresh";
+        }
+    }
+    CACHE_COUNTER.with_label_values(&["redis", outcome]).inc();
+    let loaded = config.flights.load(uuid, || load(db, &mut cache, config, uuid)).await?;
+    loaded.open(&config.keys, &uuid)
+}
+
+/// Whether to reload a cached text with `ttl` milliseconds left already, so that texts read often rarely expire.
+///
+/// The closer a text is to expiring and the longer loading it takes, the likelier it is reloaded
+/// (probabilistic early expiration, scaled by `cache_early_refresh`).
+fn refresh_early(config: &AppConfig, ttl: i64) -> bool {
+    if config.cache_early_refresh <= 0.0 || ttl < 0 {
+        return false;
+    }
+    let load_time = config.flights.load_time().as_secs_f64() * 1000.0;
+    load_time * config.cache_early_refresh * -rand::random::<f64>().ln() >= ttl as f64
+}
+
+/// Loads a text from the DB and caches it.
+async fn load(
+    db: &Db,
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    uuid: Uuid,
+) -> Result<Loaded, (Status, String)> {
+    let text = match find_text(db, config, uuid).await {
+        Err(error) => return Err((Status::InternalServerError, format!("failed to get DB: {}", error))),
+        Ok(None) => {
+            cache_missing(db, cache, config, uuid).await;
+            return Err((Status::NotFound, "text not found".to_owned()));
+        },
+        Ok(Some(text)) => text,
+    };
+    let expires_at = text.expires_at;
+    let loaded = Loaded::new(text)?;
+    if let Loaded::Inline(data) = &loaded {
+        cache_data(cache, config, uuid, data, expires_at).await;
+    }
+    Ok(loaded)
+}
+
+/// Where to read the data of a [`resolve`]d text from.
+fn text_data(keys: &crypto::Keyring, text: Text) -> Result<TextData, (Status, String)> {
+    let uuid = text._id;
+    Loaded::new(text)?.open(keys, &uuid)
+}
+
+/// Caches the data of a text in process and in Redis, sealed there if texts are encrypted.
+async fn cache_data(
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    uuid: Uuid,
+    data: &str,
+    expires_at: Option<bson::DateTime>,
+) {
+    let Some(ttl) = cache_ttl(expires_at) else {
+        // do not leave the text cached as missing while it still exists
+        let _: redis::RedisResult<()> = observability::redis("DEL", cache.del(config.cache_key(&uuid))).await;
+        return;
+    };
+    config.memory.insert(uuid, data, ttl).await;
+    match config.keys.seal_cached(&uuid, data) {
+        Ok(value) => {
+            let millis = usize::try_from(ttl.as_millis()).unwrap_or(usize::MAX);
+            let _: redis::RedisResult<String> = observability::redis("PSETEX", cache.pset_ex(config.cache_key(&uuid), value, millis)).await;
+        },
+        Err(error) => {
+            warn!("failed to encrypt cached data of {}: {}", uuid, error);
+            // do not leave the text cached as missing
+            let _: redis::RedisResult<()> = observability::redis("DEL", cache.del(config.cache_key(&uuid))).await;
         },
-        Ok(None) => (Status::NotFound, "text not found".to_owned()),
     }
 }
 
+/// Remembers that a text does not exist, unless it was cached since it was looked up.
+///
+/// Texts that are stored without being cached, like those in GridFS, only have
+/// their key deleted, which may happen before it is set here. So the text is
+/// looked up once more and forgotten as missing if it was stored meanwhile.
+async fn cache_missing(db: &Db, cache: &mut Connection<Cache>, config: &AppConfig, uuid: Uuid) {
+    if config.negative_cache_ttl == 0 {
+        return;
+    }
+    let mut set = redis::cmd("SET");
+    set.arg(config.cache_key(&uuid)).arg(MISSING).arg("EX").arg(config.negative_cache_ttl).arg("NX");
+    let set: redis::RedisResult<Option<String>> = observability::redis("SET", set.query_async(&mut **cache)).await;
+    if !matches!(set, Ok(Some(_))) {
+        return;
+    }
+    let filter = doc! { "_id": uuid_to_bson(&uuid), "expires_at": not_expired() };
+    if !matches!(observability::mongo("count_documents", config.texts(db).count_documents(filter, None)).await, Ok(0)) {
+        forget_missing(cache, config, &[uuid]).await;
+    }
+}
+
+/// Forgets that texts were missing, once they were created with IDs chosen by the client or looked up again.
+async fn forget_missing(cache: &mut Connection<Cache>, config: &AppConfig, uuids: &[Uuid]) {
+    if uuids.is_empty() {
+        return;
+    }
+    let keys: Vec<String> = uuids.iter().map(|uuid| config.cache_key(uuid)).collect();
+    let _: redis::RedisResult<()> = observability::redis("DEL", cache.del(keys)).await;
+}
+
+/// Removes a text from both caches, and from those of the other replicas.
+async fn uncache(cache: &mut deadpool_redis::Connection, config: &AppConfig, uuid: Uuid) {
+    config.memory.invalidate(&uuid).await;
+    let _: redis::RedisResult<()> = observability::redis("DEL", cache.del(config.cache_key(&uuid))).await;
+    config.invalidator.publish(cache, uuid).await;
+}
+
+/// Loads a text that has not expired yet, resolving shared content into its data.
+async fn find_text(db: &Db, config: &AppConfig, uuid: Uuid) -> mongodb::error::Result<Option<Text>> {
+    let filter = doc! { "_id": uuid_to_bson(&uuid), "expires_at": not_expired() };
+    match observability::mongo("find_one", config.texts(db).find_one(filter, None)).await? {
+        None => Ok(None),
+        Some(text) => resolve(db, &config.keys, text).await.map(Some),
+    }
+}
+
+/// Fills in the data of a text whose storage is shared with other texts, encrypted or compressed.
+///
+/// The data of texts in GridFS is left to be streamed.
+async fn resolve(db: &Db, keys: &crypto::Keyring, mut text: Text) -> mongodb::error::Result<Text> {
+    if let (None, Some(hash)) = (&text.data, &text.content) {
+        text.data = dedup::load(db, hash).await?;
+    }
+    if let Some(wrapped) = text.key.as_ref().filter(|_| text.file.is_none()) {
+        let data_key = keys.unwrap(wrapped, &text._id)?;
+        if let Some(compressed) = &mut text.compressed {
+            compressed.data.bytes = data_key.open(&compressed.data.bytes)?;
+        }
+        if let Some(sealed) = text.sealed.take() {
+            let data = String::from_utf8(data_key.open(&sealed.bytes)?)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            text.data = Some(data);
+        }
+    }
+    if let Some(compressed) = text.compressed.take() {
+        text.data = Some(compression::decompress(&compressed)?);
+    }
+    Ok(text)
+}
+
+/// Encrypts a text stored in plain under a new data key.
+///
+/// Returns the text to store instead and the storage only the plain text used,
+/// to be [`discard`]ed once it was replaced. Shared content is not encrypted in
+/// place, as other texts may use it, but read into the text.
+async fn encrypt(db: &Db, keys: &crypto::Keyring, text: Text) -> mongodb::error::Result<(Text, Text)> {
+    let (key, data_key) = keys.generate(&text._id)?.ok_or_else(|| io::Error::other("no encryption key is active"))?;
+    let plain = Text { _id: text._id, content: text.content.clone(), file: text.file, ..Default::default() };
+    let mut text = Text { key: Some(key), content: None, ..text };
+    if let Some(file) = text.file {
+        text.file = Some(gridfs::encrypt(db, text._id, file, data_key, text.expires_at).await?);
+        return Ok((text, plain));
+    }
+    if let (None, Some(hash)) = (&text.data, &plain.content) {
+        let data = dedup::load(db, hash).await?;
+        text.data = Some(data.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("content {} not found", hash)))?);
+    }
+    if let Some(compressed) = &mut text.compressed {
+        compressed.data = crypto::binary(data_key.seal(&compressed.data.bytes)?);
+    } else if let Some(data) = text.data.take() {
+        text.sealed = Some(crypto::binary(data_key.seal(data.as_bytes())?));
+    }
+    Ok((text, plain))
+}
+
+/// Condition on `expires_at` matching texts that have not expired yet.
+///
+/// The TTL monitor only sweeps about once a minute, so reads have to filter out expired texts themselves.
+fn not_expired() -> bson::Bson {
+    bson::bson!({ "$not": { "$lte": bson::DateTime::now() } })
+}
+
 #[delete("/texts/<uuid>")]
-async fn delete_text(mongo: Connection<Store>, mut cache: Connection<Cache>, uuid: Uuid) -> (Status, Value) {
-    let _ = cache.del::<String, String>(uuid.to_string()).await;
-    let collection = mongo.database("erfa").collection::<Text>("texts");
-    match collection.delete_one(doc! { "_id": uuid_to_bson(&uuid) }, None).await {
+async fn delete_text(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    uuid: Uuid,
+) -> (Status, Value) {
+    let db = config.database(&mongo);
+    let pending = config.background.tombstone(&mut cache, config, uuid).await;
+    let deleted = observability::mongo("find_one_and_delete", config.texts(&db).find_one_and_delete(doc! { "_id": uuid_to_bson(&uuid) }, None)).await;
+    // invalidated only once deleted, so that reads in between do not cache the text again
+    uncache(&mut cache, config, uuid).await;
+    match deleted {
         Err(error) => (Status::InternalServerError, json!({
             "error": format!("failed to delete from DB: {}", error)
         })),
-        Ok(res) => {
-            if res.deleted_count > 0 {
-                (Status::NoContent, Value::default())
-            } else {
-                (Status::Gone, Value::default())
+        Ok(Some(text)) => {
+            discard(&db, &text).await;
+            if let Err(error) = original::remove(&db, uuid).await {
+                warn!("failed to remove original of {}: {}", uuid, error);
             }
-        }
+            if let Err(error) = history::remove_all(&db, uuid).await {
+                warn!("failed to remove versions of {}: {}", uuid, error);
+            }
+            (Status::NoContent, Value::default())
+        },
+        // the text is removed once it is stored
+        Ok(None) if pending => (Status::NoContent, Value::default()),
+        Ok(None) => (Status::NotFound, json!({ "error": "text not found" })),
     }
 }
 
+/// Resolves the optional `ttl_seconds` or `expires_at` of a message into an absolute expiry.
+fn expiry(ttl_seconds: Option<u64>, expires_at: Option<&str>) -> Result<Option<bson::DateTime>, String> {
+    let now = bson::DateTime::now();
+    let expires_at = match (ttl_seconds, expires_at) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => return Err("only one of ttl_seconds and expires_at may be set".to_owned()),
+        (Some(ttl), None) => {
+            let millis = i64::try_from(ttl).ok()
+                .and_then(|ttl| ttl.checked_mul(1000))
+                .and_then(|ttl| ttl.checked_add(now.timestamp_millis()))
+                .ok_or_else(|| format!("ttl_seconds out of range: {}", ttl))?;
+            bson::DateTime::from_millis(millis)
+        },
+        (None, Some(expires_at)) => bson::DateTime::parse_rfc3339_str(expires_at)
+            .map_err(|error| format!("invalid expires_at: {}", error))?,
+    };
+    if expires_at <= now {
+        return Err("expiry must be in the future".to_owned());
+    }
+    Ok(Some(expires_at))
+}
+
+/// How long a text may stay cached, never outliving the text itself, or `None` if it expires too soon to be cached.
+fn cache_ttl(expires_at: Option<bson::DateTime>) -> Option<Duration> {
+    let Some(expires_at) = expires_at else {
+        return Some(EXPIRE);
+    };
+    let remaining = expires_at.timestamp_millis() - bson::DateTime::now().timestamp_millis();
+    let remaining = Duration::from_millis(u64::try_from(remaining).ok()?);
+    (remaining >= MIN_CACHE_TTL).then(|| remaining.min(EXPIRE))
+}
+
 fn uuid_to_bson(uuid: &Uuid) -> bson::Bson {
     let options = bson::ser::SerializerOptions::builder().human_readable(false).build();
     bson::to_bson_with_options(&uuid, options).unwrap()
 }
 
+/// Reads the app config and loads the encryption keys it refers to.
+async fn configure(rocket: Rocket<Build>) -> fairing::Result {
+    let mut config = match rocket.figment().extract::<AppConfig>() {
+        Ok(config) => config,
+        Err(error) => {
+            error!("invalid app config: {}", error);
+            return Err(rocket);
+        },
+    };
+    if let Err(error) = schema::check_names(&config.mongo_database, &config.mongo_collection) {
+        error!("invalid app config: {}", error);
+        return Err(rocket);
+    }
+    if !(config.cache_early_refresh >= 0.0 && config.cache_early_refresh.is_finite()) {
+        error!("invalid app config: cache_early_refresh must be a non-negative number");
+        return Err(rocket);
+    }
+    let env = env::var(crypto::KEYS_ENV).ok();
+    match crypto::Keyring::load(config.encryption_keyfile.as_deref(), env.as_deref(), config.encryption_key.as_deref()) {
+        Ok(keys) => config.keys = Arc::new(keys),
+        Err(error) => {
+            error!("failed to load encryption keys: {}", error);
+            return Err(rocket);
+        },
+    }
+    config.memory = memory::MemoryCache::new(config.memory_cache_bytes, Duration::from_secs(config.memory_cache_ttl));
+    config.invalidator = invalidation::Invalidator::new(&config.mongo_database, &config.mongo_collection);
+    if config.cache_policy == write::CachePolicy::Behind {
+        let Some(cache) = Cache::fetch(&rocket) else {
+            return Err(rocket);
+        };
+        config.background = write::Background::new((**cache).clone());
+    }
+    Ok(rocket.manage(config))
+}
+
+/// Sets up indexes and validators, refusing to start on texts stored in another layout.
+async fn bootstrap_schema(rocket: Rocket<Build>) -> fairing::Result {
+    let (Some(store), Some(config)) = (Store::fetch(&rocket), rocket.state::<AppConfig>()) else {
+        return Err(rocket);
+    };
+    if let Err(error) = schema::bootstrap(&config.database(store), &config.mongo_collection).await {
+        error!("{}", error);
+        return Err(rocket);
+    }
+    Ok(rocket)
+}
+
+/// Periodically deletes GridFS files of expired texts.
+fn sweep_files(rocket: &Rocket<Orbit>) {
+    let (Some(store), Some(config)) = (Store::fetch(rocket), rocket.state::<AppConfig>()) else {
+        return;
+    };
+    let db = config.database(store);
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match gridfs::sweep(&db).await {
+                Ok(0) => {},
+                Ok(deleted) => info!("deleted {} files of expired texts", deleted),
+                Err(error) => warn!("failed to delete files of expired texts: {}", error),
+            }
+        }
+    });
+}
+
+/// Evicts texts other replicas replaced or deleted from the in-process cache.
+fn subscribe_invalidations(rocket: &Rocket<Orbit>) {
+    let Some(config) = rocket.state::<AppConfig>().filter(|config| config.memory.is_enabled()) else {
+        return;
+    };
+    let url = match rocket.figment().extract_inner::<String>("databases.redis.url") {
+        Ok(url) => url,
+        Err(error) => {
+            warn!("not subscribing to cache invalidations: {}", error);
+            return;
+        },
+    };
+    rocket::tokio::spawn(config.invalidator.clone().subscribe(url, config.memory.clone()));
+}
+
 #[catch(500)]
 fn internal_error() -> Value {
     json!({
@@ -145,20 +1063,19 @@ fn not_found(req: &Request) -> Value {
 
 #[launch]
 fn rocket() -> _ {
-    let prometheus = PrometheusMetrics::new();
-    prometheus
-        .registry()
-        .register(Box::new(TERM_COUNTER.clone()))
-        .unwrap();
-    prometheus
-        .registry()
-        .register(Box::new(CACHE_COUNTER.clone()))
-        .unwrap();
+    observability::metrics::register();
+    prometheus::register(Box::new(terms::TermsCollector::new())).unwrap();
+    prometheus::register(Box::new(COMPRESSION_RATIO.clone())).unwrap();
     rocket::build()
-        .attach(prometheus.clone())
+        .attach(observability::rocket::Track)
         .attach(Cache::init())
         .attach(Store::init())
-        .register("/", catchers![internal_error, not_found])
-        .mount("/", routes![store_text, delete_text, get_text, search_text])
-        .mount("/metrics", prometheus)
+        .attach(AdHoc::try_on_ignite("App config", configure))
+        .attach(AdHoc::try_on_ignite("Mongo schema", bootstrap_schema))
+        .attach(observability::rocket::tracing(SERVICE))
+        .attach(AdHoc::on_liftoff("GridFS sweeper", |rocket| Box::pin(async move { sweep_files(rocket) })))
+        .attach(AdHoc::on_liftoff("Cache invalidation", |rocket| Box::pin(async move { subscribe_invalidations(rocket) })))
+        .register("/", catchers![internal_error, not_found, admin::unauthorized, admin::forbidden])
+        .mount("/", observability::rocket::traced(routes![store_text, upload::store_document, upload::store_file, replace_text, delete_text, get_text, search_text, bulk::import_texts, export::export_texts, original::get_original, history::list_versions, history::get_version, history::diff_versions, terms::top_terms, admin::reencrypt]))
+        .mount("/", routes![observability::rocket::export])
 }
@@ -0,0 +1,181 @@
+//! In-process cache of text data, in front of Redis.
+//!
+//! Entries are weighed by their size and admitted by TinyLFU, so texts read
+//! once do not push out those read often. Entries live no longer than in Redis
+//! and at most `memory_cache_ttl`, which bounds how long a replica can serve a
+//! text that another replica replaced or deleted, should an eviction
+//! published by [`crate::invalidation`] get lost.
+//!
+//! Once a text is searched, the words it contains are kept with it, so that
+//! later searches look them up instead of splitting the text again. The entry
+//! is stored again then, to be weighed with its words.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use moka::future::Cache;
+use moka::ops::compute::Op;
+use moka::Expiry;
+use rocket::serde::uuid::Uuid;
+
+/// Bytes an entry takes besides its data.
+const ENTRY_OVERHEAD: usize = 64;
+
+#[derive(Clone)]
+struct Entry {
+    data: Arc<str>,
+    /// Built when the text is first searched.
+    words: Option<Arc<Words>>,
+    expires_at: Instant,
+}
+
+impl Entry {
+    fn weight(&self) -> u32 {
+        let words = self.words.as_ref().map_or(0, |words| words.size());
+        u32::try_from(self.data.len() + words + ENTRY_OVERHEAD).unwrap_or(u32::MAX)
+    }
+}
+
+/// Hashes of the distinct words of a text.
+///
+/// Words are only told apart by their hashes, which are keyed randomly so that
+/// collisions cannot be provoked.
+struct Words {
+    state: RandomState,
+    hashes: HashSet<u64>,
+}
+
+impl Words {
+    fn new(data: &str) -> Words {
+        let state = RandomState::new();
+        let hashes = data.split_whitespace().map(|word| state.hash_one(word)).collect();
+        Words { state, hashes }
+    }
+
+    fn contains(&self, word: &str) -> bool {
+        self.hashes.contains(&self.state.hash_one(word))
+    }
+
+    /// Bytes taken by the hashes, each with the control byte of its slot.
+    fn size(&self) -> usize {
+        self.hashes.capacity() * (size_of::<u64>() + 1)
+    }
+}
+
+struct EntryExpiry;
+
+impl Expiry<Uuid, Entry> for EntryExpiry {
+    fn expire_after_create(&self, _: &Uuid, entry: &Entry, now: Instant) -> Option<Duration> {
+        Some(entry.expires_at.saturating_duration_since(now))
+    }
+
+    fn expire_after_update(&self, _: &Uuid, entry: &Entry, now: Instant, _: Option<Duration>) -> Option<Duration> {
+        Some(entry.expires_at.saturating_duration_since(now))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryCache {
+    /// `None` if the cache is disabled.
+    entries: Option<Cache<Uuid, Entry>>,
+    max_ttl: Duration,
+    /// Whether evictions of other replicas are received, else the cache is bypassed.
+    synced: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for MemoryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (entries, bytes) = self.entries.as_ref().map_or((0, 0), |entries| (entries.entry_count(), entries.weighted_size()));
+        f.debug_struct("MemoryCache").field("entries", &entries).field("bytes", &bytes).finish()
+    }
+}
+
+impl MemoryCache {
+    /// A cache holding up to `max_bytes` of texts for up to `max_ttl`, disabled if either is zero.
+    pub fn new(max_bytes: u64, max_ttl: Duration) -> MemoryCache {
+        if max_bytes == 0 || max_ttl.is_zero() {
+            return MemoryCache::default();
+        }
+        let entries = Cache::builder()
+            .max_capacity(max_bytes)
+            .weigher(|_, entry: &Entry| entry.weight())
+            .expire_after(EntryExpiry)
+            .build();
+        MemoryCache { entries: Some(entries), max_ttl, synced: Arc::default() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    pub fn set_synced(&self, synced: bool) {
+        self.synced.store(synced, Ordering::Release);
+    }
+
+    fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Acquire)
+    }
+
+    pub async fn get(&self, uuid: &Uuid) -> Option<String> {
+        if !self.is_synced() {
+            return None;
+        }
+        let entry = self.entries.as_ref()?.get(uuid).await?;
+        Some(entry.data.to_string())
+    }
+
+    /// Whether a cached text contains a word, `None` if the text is not cached.
+    pub async fn contains_word(&self, uuid: &Uuid, word: &str) -> Option<bool> {
+        if !self.is_synced() {
+            return None;
+        }
+        let entries = self.entries.as_ref()?;
+        let entry = entries.get(uuid).await?;
+        if let Some(words) = &entry.words {
+            return Some(words.contains(word));
+        }
+        let words = Arc::new(Words::new(&entry.data));
+        let contains = words.contains(word);
+        // unless the text was replaced or evicted meanwhile
+        entries
+            .entry(*uuid)
+            .and_compute_with(|current| async move {
+                match current.map(|current| current.into_value()) {
+                    Some(current) if Arc::ptr_eq(&current.data, &entry.data) && current.words.is_none() => {
+                        Op::Put(Entry { words: Some(words), ..current })
+                    },
+                    _ => Op::Nop,
+                }
+            })
+            .await;
+        Some(contains)
+    }
+
+    /// Caches the data of a text for `ttl`, capped to the configured maximum.
+    pub async fn insert(&self, uuid: Uuid, data: &str, ttl: Duration) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+        let ttl = ttl.min(self.max_ttl);
+        if self.is_synced() && !ttl.is_zero() {
+            entries.insert(uuid, Entry { data: Arc::from(data), words: None, expires_at: Instant::now() + ttl }).await;
+        }
+    }
+
+    pub async fn invalidate(&self, uuid: &Uuid) {
+        if let Some(entries) = &self.entries {
+            entries.invalidate(uuid).await;
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        if let Some(entries) = &self.entries {
+            entries.invalidate_all();
+        }
+    }
+}
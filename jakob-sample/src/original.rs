@@ -0,0 +1,117 @@
+//! Uploaded documents kept next to the text extracted from them.
+//!
+//! Originals of encrypted texts are sealed under the data key of their text, a
+//! copy of which is kept with them.
+
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{json, Value};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_db_pools::mongodb::bson::{self, doc, spec::BinarySubtype};
+use rocket_db_pools::mongodb::{self, Collection};
+use rocket_db_pools::Connection;
+
+use crate::crypto::{self, Keyring, WrappedKey};
+use crate::{not_expired, uuid_to_bson, AppConfig, Db, Store};
+
+pub const COLLECTION: &str = "originals";
+
+/// An uploaded document as it was received.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub content_type: ContentType,
+    pub file_name: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Original {
+    _id: Uuid,
+    content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_name: Option<String>,
+    /// Sealed if `key` is set.
+    data: bson::Binary,
+    /// Same as the key of the text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<WrappedKey>,
+    /// Same as the expiry of the text, so that both are swept together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<bson::DateTime>,
+}
+
+fn originals(db: &Db) -> Collection<Original> {
+    db.collection::<Original>(&db.collection_name(COLLECTION))
+}
+
+/// Stores the original of a text, sealed under `key` if the text is encrypted.
+pub async fn store(
+    db: &Db,
+    keys: &Keyring,
+    id: Uuid,
+    attachment: &Attachment,
+    key: Option<&WrappedKey>,
+    expires_at: Option<bson::DateTime>,
+) -> mongodb::error::Result<()> {
+    let data = match key {
+        Some(key) => crypto::binary(keys.unwrap(key, &id)?.seal(&attachment.data)?),
+        None => bson::Binary { subtype: BinarySubtype::Generic, bytes: attachment.data.clone() },
+    };
+    let original = Original {
+        _id: id,
+        content_type: attachment.content_type.to_string(),
+        file_name: attachment.file_name.clone(),
+        data,
+        key: key.cloned(),
+        expires_at,
+    };
+    observability::mongo("insert_one", originals(db).insert_one(original, None)).await?;
+    Ok(())
+}
+
+pub async fn remove(db: &Db, id: Uuid) -> mongodb::error::Result<()> {
+    observability::mongo("delete_one", originals(db).delete_one(doc! { "_id": uuid_to_bson(&id) }, None)).await?;
+    Ok(())
+}
+
+#[get("/texts/<uuid>/original")]
+pub async fn get_original(mongo: Connection<Store>, config: &State<AppConfig>, uuid: Uuid) -> Result<Attachment, (Status, Value)> {
+    let filter = doc! { "_id": uuid_to_bson(&uuid), "expires_at": not_expired() };
+    match observability::mongo("find_one", originals(&config.database(&mongo)).find_one(filter, None)).await {
+        Err(error) => Err((Status::InternalServerError, json!({
+            "error": format!("failed to get DB: {}", error)
+        }))),
+        Ok(None) => Err((Status::NotFound, json!({ "error": "original not found" }))),
+        Ok(Some(original)) => {
+            let data = match &original.key {
+                Some(key) => config.keys.unwrap(key, &uuid).and_then(|data_key| data_key.open(&original.data.bytes)).map_err(|error| {
+                    (Status::InternalServerError, json!({ "error": format!("failed to decrypt original: {}", error) }))
+                })?,
+                None => original.data.bytes,
+            };
+            Ok(Attachment {
+                content_type: ContentType::parse_flexible(&original.content_type).unwrap_or(ContentType::Binary),
+                file_name: original.file_name,
+                data,
+            })
+        },
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Attachment {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.header(self.content_type);
+        if let Some(file_name) = self.file_name {
+            let disposition = format!("attachment; filename=\"{}\"", file_name.replace(['"', '\\'], "_"));
+            response.header(Header::new("Content-Disposition", disposition));
+        }
+        response.sized_body(self.data.len(), Cursor::new(self.data)).ok()
+    }
+}
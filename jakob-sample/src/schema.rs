@@ -0,0 +1,86 @@
+//! Indexes and validation of the collections, set up when the server starts.
+//!
+//! Other servers of this project store texts with a string `_id` or their data
+//! in a `text` field. Such texts cannot be read here, so starting on top of
+//! them fails instead of answering 404 or 500 for each of them later. The
+//! check and the validator of the texts are shared with the other servers, see
+//! [`text_schema::JAKOB`].
+//!
+//! The texts collection is named in the config, see [`check_names`], and the
+//! other collections after it, see [`crate::Db::collection_name`].
+
+use std::time::Duration;
+
+use rocket_db_pools::mongodb::bson::{doc, Document};
+use rocket_db_pools::mongodb::options::{FindOneOptions, IndexOptions};
+use rocket_db_pools::mongodb::{self, Database, IndexModel};
+use text_schema::JAKOB;
+
+use crate::{dedup, history, original, Db};
+
+/// Checks the layout of stored texts, then creates the indexes and validators the server relies on.
+pub async fn bootstrap(db: &Db, texts: &str) -> Result<(), String> {
+    let options = FindOneOptions::builder().projection(doc! { "_id": 1 }).build();
+    let found = db
+        .collection::<Document>(texts)
+        .find_one(JAKOB.incompatible(), options)
+        .await
+        .map_err(|error| format!("failed to check the layout of {}: {}", texts, error))?;
+    JAKOB.check(texts, found.as_ref())?;
+    validate(db, texts)
+        .await
+        .map_err(|error| format!("failed to set the validator of {}: {}", texts, error))?;
+    let index = IndexModel::builder()
+        .keys(doc! { "text_id": 1, "n": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let versions = db.collection_name(history::COLLECTION);
+    db.collection::<Document>(&versions)
+        .create_index(index, None)
+        .await
+        .map_err(|error| format!("failed to create index on {}: {}", versions, error))?;
+    for name in [texts.to_owned(), db.collection_name(original::COLLECTION), versions] {
+        let index = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+            .build();
+        db.collection::<Document>(&name)
+            .create_index(index, None)
+            .await
+            .map_err(|error| format!("failed to create TTL index on {}: {}", name, error))?;
+    }
+    Ok(())
+}
+
+/// Checks the names of the database and the texts collection, which must not be taken by the other collections.
+pub fn check_names(database: &str, texts: &str) -> Result<(), String> {
+    text_schema::check_names(database, texts)?;
+    // the other collections are named after the texts collection, those of the default one without its name
+    let reserved = [original::COLLECTION, history::COLLECTION, dedup::COLLECTION, "large_texts.files", "large_texts.chunks"];
+    if reserved.iter().any(|name| texts == *name || texts.ends_with(&format!(".{}", name))) {
+        return Err(format!("collection name {:?} is reserved", texts));
+    }
+    Ok(())
+}
+
+/// Sets the validator of the texts, creating their collection if it does not exist yet.
+async fn validate(db: &Database, texts: &str) -> mongodb::error::Result<()> {
+    let exists = !db.list_collection_names(doc! { "name": texts }).await?.is_empty();
+    db.run_command(JAKOB.validate_command(texts, exists), None).await.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_collections_are_reserved() {
+        assert_eq!(check_names("erfa", "texts"), Ok(()));
+        assert_eq!(check_names("erfa", "tenant.texts"), Ok(()));
+        assert!(check_names("erfa", "versions").is_err());
+        assert!(check_names("erfa", "tenant.contents").is_err());
+        assert!(check_names("erfa", "texts.large_texts.files").is_err());
+        assert!(check_names("erfa", "system.texts").is_err());
+        assert!(check_names("er.fa", "texts").is_err());
+    }
+}
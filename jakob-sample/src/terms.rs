@@ -0,0 +1,253 @@
+//! The terms searched for most, estimated in bounded memory.
+//!
+//! Terms are counted with the Space-Saving algorithm: a fixed number of
+//! counters is kept, and a term that has none takes over the smallest one,
+//! inheriting its count as possible overestimation. Every term searched for
+//! more than 1/[`CAPACITY`] of the time is guaranteed to have a counter.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use rocket::serde::json::{json, Value};
+use rocket::serde::Serialize;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{opts, IntGaugeVec};
+
+/// Number of terms counted.
+const CAPACITY: usize = 1000;
+/// Number of terms exported as metrics, all others are summed up as [`OTHER`].
+const EXPORTED: usize = 20;
+/// Longer terms are counted by their first bytes.
+const MAX_TERM_LEN: usize = 128;
+/// Label of the terms not exported, including any term that is literally "other".
+const OTHER: &str = "other";
+/// Terms returned by `/stats/top-terms` unless a limit is given.
+const DEFAULT_LIMIT: usize = 10;
+
+pub static TERMS: Lazy<TopTerms> = Lazy::new(|| TopTerms::new(CAPACITY));
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TermCount {
+    term: String,
+    /// Upper bound of how often the term was searched for.
+    count: u64,
+    /// How much `count` may be overestimated by.
+    error: u64,
+}
+
+pub struct TopTerms(Mutex<SpaceSaving>);
+
+struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<Arc<str>, Counter>,
+    /// The same counters ordered by count, to find the smallest.
+    by_count: BTreeSet<(u64, Arc<str>)>,
+    /// Searches counted, including those of terms that lost their counter.
+    total: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Counter {
+    count: u64,
+    error: u64,
+}
+
+impl TopTerms {
+    fn new(capacity: usize) -> TopTerms {
+        TopTerms(Mutex::new(SpaceSaving {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+            by_count: BTreeSet::new(),
+            total: 0,
+        }))
+    }
+
+    pub fn record(&self, term: &str) {
+        self.0.lock().unwrap().record(truncate(term));
+    }
+
+    /// The terms with the highest counts, and the number of all searches.
+    pub fn top(&self, limit: usize) -> (Vec<TermCount>, u64) {
+        let terms = self.0.lock().unwrap();
+        let top = terms
+            .by_count
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|(count, term)| TermCount { term: term.to_string(), count: *count, error: terms.counters[term].error })
+            .collect();
+        (top, terms.total)
+    }
+}
+
+impl SpaceSaving {
+    fn record(&mut self, term: &str) {
+        self.total += 1;
+        if let Some((term, counter)) = self.counters.get_key_value(term) {
+            let (term, counter) = (term.clone(), *counter);
+            self.set(term, Counter { count: counter.count + 1, ..counter }, Some(counter.count));
+            return;
+        }
+        if self.counters.len() < self.capacity {
+            self.set(Arc::from(term), Counter { count: 1, error: 0 }, None);
+            return;
+        }
+        let Some((min, evicted)) = self.by_count.pop_first() else {
+            return;
+        };
+        self.counters.remove(&evicted);
+        self.set(Arc::from(term), Counter { count: min + 1, error: min }, None);
+    }
+
+    fn set(&mut self, term: Arc<str>, counter: Counter, previous: Option<u64>) {
+        if let Some(previous) = previous {
+            self.by_count.remove(&(previous, term.clone()));
+        }
+        self.by_count.insert((counter.count, term.clone()));
+        self.counters.insert(term, counter);
+    }
+}
+
+fn truncate(term: &str) -> &str {
+    if term.len() <= MAX_TERM_LEN {
+        return term;
+    }
+    let mut end = MAX_TERM_LEN;
+    while !term.is_char_boundary(end) {
+        end -= 1;
+    }
+    &term[..end]
+}
+
+/// Exports the counts of the top terms, and of all others summed up, as gauges.
+///
+/// Gauges, as a term that loses its counter to another one drops out of the
+/// top terms and into the others. The others are all searches but those the
+/// top terms are known to have had, their counts less the overestimation, so
+/// that neither gauge is underestimated and all of them may add up to more
+/// than the searches counted.
+pub struct TermsCollector {
+    gauges: IntGaugeVec,
+}
+
+impl TermsCollector {
+    pub fn new() -> TermsCollector {
+        TermsCollector { gauges: terms_gauges() }
+    }
+}
+
+fn terms_gauges() -> IntGaugeVec {
+    IntGaugeVec::new(opts!("search_term_top_count", "Estimated count of times the most searched terms were searched for"), &["term"])
+        .expect("Could not create IntGaugeVec")
+}
+
+impl Collector for TermsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauges.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let (top, total) = TERMS.top(EXPORTED);
+        let gauges = terms_gauges();
+        let mut other = total;
+        for TermCount { term, count, error } in top.iter().filter(|top| top.term != OTHER) {
+            gauges.with_label_values(&[term]).set(*count as i64);
+            other = other.saturating_sub(count - error);
+        }
+        gauges.with_label_values(&[OTHER]).set(other as i64);
+        gauges.collect()
+    }
+}
+
+#[get("/stats/top-terms?<limit>")]
+pub fn top_terms(limit: Option<usize>) -> Value {
+    let (top, total) = TERMS.top(limit.unwrap_or(DEFAULT_LIMIT).min(CAPACITY));
+    json!({ "total": total, "terms": top })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(terms: &TopTerms) -> HashMap<String, (u64, u64)> {
+        let (top, _) = terms.top(usize::MAX);
+        top.into_iter().map(|top| (top.term, (top.count, top.error))).collect()
+    }
+
+    #[test]
+    fn exact_below_capacity() {
+        let terms = TopTerms::new(3);
+        for term in ["a", "b", "a", "c", "a", "b"] {
+            terms.record(term);
+        }
+        let (top, total) = terms.top(2);
+        assert_eq!(total, 6);
+        assert_eq!(top.iter().map(|top| (top.term.as_str(), top.count, top.error)).collect::<Vec<_>>(), [("a", 3, 0), ("b", 2, 0)]);
+    }
+
+    #[test]
+    fn new_term_evicts_smallest_counter() {
+        let terms = TopTerms::new(2);
+        for term in ["a", "a", "b", "c"] {
+            terms.record(term);
+        }
+        let counts = counts(&terms);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["a"], (2, 0));
+        assert_eq!(counts["c"], (2, 1));
+        assert!(!counts.contains_key("b"));
+    }
+
+    #[test]
+    fn overflow_keeps_capacity_and_total() {
+        let terms = TopTerms::new(10);
+        for n in 0..1000 {
+            terms.record(&format!("term {}", n));
+        }
+        let (top, total) = terms.top(usize::MAX);
+        assert_eq!(total, 1000);
+        assert_eq!(top.len(), 10);
+        // the counts of all counters always add up to the searches counted
+        assert_eq!(top.iter().map(|top| top.count).sum::<u64>(), 1000);
+    }
+
+    #[test]
+    fn error_is_bounded() {
+        let capacity = 20;
+        let terms = TopTerms::new(capacity);
+        let mut actual: HashMap<String, u64> = HashMap::new();
+        // skewed so that a few terms are frequent and many are rare
+        let mut state: u64 = 42;
+        for _ in 0..20_000 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let n = (state >> 33) % 1000;
+            let term = format!("term {}", n * n / 1000);
+            terms.record(&term);
+            *actual.entry(term).or_default() += 1;
+        }
+        let (top, total) = terms.top(usize::MAX);
+        let bound = total / capacity as u64;
+        for TermCount { term, count, error } in &top {
+            let actual = actual.get(term).copied().unwrap_or(0);
+            assert!(count - error <= actual && actual <= *count, "{}: {} not within {}-{}", term, actual, count, error);
+            assert!(*error <= bound);
+        }
+        for (term, actual) in &actual {
+            if *actual > bound {
+                assert!(top.iter().any(|top| &top.term == term), "frequent term {} has no counter", term);
+            }
+        }
+    }
+
+    #[test]
+    fn long_terms_truncated_at_characters() {
+        let term = format!("a{}", "ß".repeat(MAX_TERM_LEN));
+        let truncated = truncate(&term);
+        assert_eq!(truncated.len(), MAX_TERM_LEN - 1);
+        assert!(term.starts_with(truncated));
+        assert_eq!(truncate("short"), "short");
+    }
+}
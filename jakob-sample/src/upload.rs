@@ -0,0 +1,231 @@
+//! Texts posted as a raw document body or as a file in a multipart form.
+//!
+//! Plain text is stored as is, other documents are stored as the text
+//! extracted from them and kept as an [`Attachment`] next to it. Raw plain
+//! text bodies above the GridFS threshold are written to GridFS as they are
+//! read, up to the `text` limit.
+
+use std::borrow::Cow;
+use std::io::{self, Cursor};
+
+use rocket::data::{self, ByteUnit, Data, DataStream, FromData};
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::serde::json::{json, Value};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use crate::extract::{self, Format};
+use crate::idempotency::{self, IdempotencyKey};
+use crate::original::Attachment;
+use crate::{discard, gridfs, record, reserve, store_message, AppConfig, Cache, Message, Store, Text};
+
+/// Limit of raw plain text bodies unless configured otherwise.
+const DEFAULT_TEXT_LIMIT: ByteUnit = ByteUnit::Gibibyte(1);
+
+#[derive(FromForm)]
+pub struct Expiry {
+    ttl_seconds: Option<u64>,
+    expires_at: Option<String>,
+}
+
+/// A raw document body, read into memory unless it is a large plain text.
+pub enum Body<'r> {
+    Buffered(Vec<u8>),
+    Streamed(Box<Streamed<'r>>),
+}
+
+/// A plain text body above the GridFS threshold.
+pub struct Streamed<'r> {
+    /// What was read to find that the body is above the threshold.
+    head: Vec<u8>,
+    rest: DataStream<'r>,
+    /// Limit of the whole body in bytes.
+    limit: u64,
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Body<'r> {
+    type Error = io::Error;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let threshold = req.rocket().state::<AppConfig>().map_or(u64::MAX, |config| config.gridfs_threshold as u64);
+        if !matches!(Format::of(req.content_type(), None), Ok(Format::Plain)) {
+            return <Vec<u8>>::from_data(req, data).await.map(Body::Buffered);
+        }
+        let limit = req.limits().get("text").unwrap_or(DEFAULT_TEXT_LIMIT);
+        // one byte over the limit, so that the upload can tell a body that exceeds it
+        let mut stream = data.open(limit + 1);
+        let mut head = Vec::new();
+        if let Err(error) = (&mut stream).take(threshold + 1).read_to_end(&mut head).await {
+            return data::Outcome::Error((Status::BadRequest, error));
+        }
+        if head.len() as u64 <= threshold {
+            return data::Outcome::Success(Body::Buffered(head));
+        }
+        data::Outcome::Success(Body::Streamed(Box::new(Streamed { head, rest: stream, limit: limit.as_u64() })))
+    }
+}
+
+#[derive(FromForm)]
+pub struct Upload<'r> {
+    file: TempFile<'r>,
+    ttl_seconds: Option<u64>,
+    expires_at: Option<String>,
+}
+
+/// Takes any body not handled by the JSON and multipart routes, answering unsupported formats with a 415.
+#[post("/texts?<expiry..>", data = "<body>", rank = 10)]
+pub async fn store_document(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    key: IdempotencyKey<'_>,
+    content_type: Option<&ContentType>,
+    expiry: Expiry,
+    body: Body<'_>,
+) -> (Status, Value) {
+    let format = match Format::of(content_type, None) {
+        Ok(format) => format,
+        Err(error) => return error.into(),
+    };
+    let charset = content_type.and_then(|content_type| content_type.param("charset"));
+    let body = match body {
+        Body::Buffered(body) => body,
+        Body::Streamed(streamed) => return store_stream(&mongo, &mut cache, config, key, charset, expiry, streamed).await,
+    };
+    let data = match extract::extract(format, &body, charset).await {
+        Ok(data) => data,
+        Err(error) => return error.into(),
+    };
+    let original = (format != Format::Plain).then(|| Attachment {
+        content_type: content_type.cloned().unwrap_or_else(|| format.content_type()),
+        file_name: None,
+        data: body,
+    });
+    let msg = Message {
+        data: Cow::Owned(data),
+        ttl_seconds: expiry.ttl_seconds,
+        expires_at: expiry.expires_at.map(Cow::Owned),
+        original,
+    };
+    store_message(&mongo, &mut cache, config, key, &msg).await
+}
+
+#[post("/texts", format = "multipart/form-data", data = "<upload>")]
+pub async fn store_file(
+    mongo: Connection<Store>,
+    mut cache: Connection<Cache>,
+    config: &State<AppConfig>,
+    key: IdempotencyKey<'_>,
+    upload: Form<Upload<'_>>,
+) -> (Status, Value) {
+    let file = &upload.file;
+    let raw_name = file.raw_name().map(|name| name.dangerous_unsafe_unsanitized_raw().as_str());
+    let format = match Format::of(file.content_type(), raw_name) {
+        Ok(format) => format,
+        Err(error) => return error.into(),
+    };
+    let mut bytes = Vec::with_capacity(file.len() as usize);
+    if let Err(error) = read_file(file, &mut bytes).await {
+        return (Status::BadRequest, json!({ "error": format!("failed to read file: {}", error) }));
+    }
+    let charset = file.content_type().and_then(|content_type| content_type.param("charset"));
+    let data = match extract::extract(format, &bytes, charset).await {
+        Ok(data) => data,
+        Err(error) => return error.into(),
+    };
+    let original = (format != Format::Plain).then(|| Attachment {
+        content_type: file
+            .content_type()
+            .filter(|content_type| !content_type.is_binary())
+            .cloned()
+            .unwrap_or_else(|| format.content_type()),
+        file_name: file.name().map(str::to_owned),
+        data: bytes,
+    });
+    let msg = Message {
+        data: Cow::Owned(data),
+        ttl_seconds: upload.ttl_seconds,
+        expires_at: upload.expires_at.as_deref().map(Cow::Borrowed),
+        original,
+    };
+    store_message(&mongo, &mut cache, config, key, &msg).await
+}
+
+/// Creates a text from a large plain text body, writing it to GridFS as it is read.
+///
+/// The idempotency key is only checked once the body was read, as the fingerprint needs all of it.
+async fn store_stream(
+    mongo: &Connection<Store>,
+    cache: &mut Connection<Cache>,
+    config: &AppConfig,
+    key: IdempotencyKey<'_>,
+    charset: Option<&str>,
+    expiry: Expiry,
+    streamed: Box<Streamed<'_>>,
+) -> (Status, Value) {
+    let expires_at = match crate::expiry(expiry.ttl_seconds, expiry.expires_at.as_deref()) {
+        Ok(expires_at) => expires_at,
+        Err(error) => return (Status::UnprocessableEntity, json!({ "error": error })),
+    };
+    let (encoding, _) = match extract::encoding(&streamed.head, charset) {
+        Ok(encoding) => encoding,
+        Err(error) => return error.into(),
+    };
+    let id = config.id_version.generate();
+    let (wrapped, data_key) = match config.keys.generate(&id) {
+        Ok(Some((wrapped, data_key))) => (Some(wrapped), Some(data_key)),
+        Ok(None) => (None, None),
+        Err(error) => return (Status::InternalServerError, json!({
+            "error": format!("failed to encrypt text: {}", error)
+        })),
+    };
+    let db = config.database(mongo);
+    let reader = Cursor::new(streamed.head).chain(streamed.rest);
+    let stored = match gridfs::upload_stream(&db, id, encoding, reader, streamed.limit, data_key, expires_at).await {
+        Ok(stored) => stored,
+        Err(error) => return error,
+    };
+    let text = Text {
+        _id: id,
+        file: Some(stored.file),
+        key: wrapped,
+        version: Some(1),
+        modified_at: Some(bson::DateTime::now()),
+        expires_at,
+        ..Default::default()
+    };
+    let fingerprint = idempotency::fingerprint(&json!({
+        "sha256": stored.digest,
+        "ttl_seconds": expiry.ttl_seconds,
+        "expires_at": expiry.expires_at,
+    }));
+    if let Some(key) = key.0 {
+        if let Err(response) = reserve(cache, config, key, &fingerprint).await {
+            discard(&db, &text).await;
+            return response;
+        }
+    }
+    let (status, body) = match observability::mongo("insert_one", config.texts(&db).insert_one(&text, None)).await {
+        Err(error) => {
+            discard(&db, &text).await;
+            (Status::InternalServerError, json!({
+                "error": format!("failed to write to DB: {}", error)
+            }))
+        },
+        Ok(_) => (Status::Created, json!({ "id": id.as_hyphenated().to_string() })),
+    };
+    if let Some(key) = key.0 {
+        record(cache, config, key, &fingerprint, status, &body).await;
+    }
+    (status, body)
+}
+
+async fn read_file(file: &TempFile<'_>, bytes: &mut Vec<u8>) -> std::io::Result<()> {
+    file.open().await?.read_to_end(bytes).await?;
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! When new texts are cached, and where texts are stored in the background.
+
+use std::fmt;
+
+use opentelemetry::context::FutureExt;
+use redis::AsyncCommands;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket_db_pools::deadpool_redis;
+use rocket_db_pools::mongodb::bson::doc;
+
+use crate::{discard, idempotency, original, uncache, uuid_to_bson, AppConfig, Db, Text, MISSING};
+
+/// Seconds a deleted text is kept from being stored by a write still pending, far longer than writes are queued.
+const TOMBSTONE_TTL: usize = 600;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub enum CachePolicy {
+    /// Cache texts once they are stored.
+    #[default]
+    #[serde(rename = "write-through")]
+    Through,
+    /// Leave texts to be cached when they are first read.
+    #[serde(rename = "write-around")]
+    Around,
+    /// Cache texts and answer right away, storing them in the background.
+    ///
+    /// A text that cannot be stored is evicted again, but its creation was confirmed already.
+    /// Its idempotency key is released, so that retrying the request creates it anew. A text
+    /// deleted before it is stored is removed again once it is, see [`Background::tombstone`].
+    #[serde(rename = "write-behind")]
+    Behind,
+}
+
+/// Stores texts after the requests creating them were answered.
+#[derive(Clone, Default)]
+pub struct Background {
+    /// `None` unless texts are written behind.
+    cache: Option<deadpool_redis::Pool>,
+}
+
+impl fmt::Debug for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Background").field("enabled", &self.cache.is_some()).finish()
+    }
+}
+
+impl Background {
+    pub fn new(cache: deadpool_redis::Pool) -> Background {
+        Background { cache: Some(cache) }
+    }
+
+    /// Inserts a cached text, evicting it again and removing its original should that fail.
+    ///
+    /// The idempotency key of the request is released then too.
+    pub fn insert(&self, db: Db, config: AppConfig, text: Text, idempotency_key: Option<String>) {
+        let cache = self.cache.clone();
+        // traced as part of the request creating the text
+        rocket::tokio::spawn(async move {
+            let error = match observability::mongo("insert_one", config.texts(&db).insert_one(&text, None)).await {
+                Ok(_) => return undo_deleted(cache, &db, &config, &text).await,
+                Err(error) => error,
+            };
+            error!("failed to write {} to DB after confirming it: {}", text._id, error);
+            discard(&db, &text).await;
+            let _ = original::remove(&db, text._id).await;
+            let connection = match cache {
+                Some(cache) => cache.get().await.map_err(|error| error.to_string()),
+                None => Err("no cache to evict from".to_owned()),
+            };
+            let mut cache = match connection {
+                Ok(cache) => cache,
+                Err(error) => {
+                    warn!("failed to evict {}, which stays cached until it expires: {}", text._id, error);
+                    return;
+                },
+            };
+            uncache(&mut cache, &config, text._id).await;
+            if let Some(key) = idempotency_key {
+                if let Err(error) = idempotency::release(&mut cache, &config.redis_prefix(), &key).await {
+                    warn!("failed to release idempotency key {} of {}: {}", key, text._id, error);
+                }
+            }
+        }.with_current_context());
+    }
+    /// Keeps a text being deleted from being stored by a write still pending, returning whether one may be.
+    ///
+    /// The writer looks for the tombstone once the text is stored, so whichever of the
+    /// two comes last removes the text. Texts written behind are cached until they are
+    /// stored, so only a cached text can be pending.
+    pub async fn tombstone(&self, cache: &mut deadpool_redis::Connection, config: &AppConfig, uuid: Uuid) -> bool {
+        if self.cache.is_none() {
+            return false;
+        }
+        let mut pipe = redis::pipe();
+        pipe.set_ex(tombstone_key(config, &uuid), 1, TOMBSTONE_TTL).ignore().get(config.cache_key(&uuid));
+        match observability::redis("PIPELINE", pipe.query_async::<_, (Option<Vec<u8>>,)>(cache)).await {
+            Ok((cached,)) => cached.is_some_and(|value| value != MISSING),
+            Err(error) => {
+                warn!("failed to mark {} deleted, which is stored again should it be written behind still: {}", uuid, error);
+                false
+            },
+        }
+    }
+}
+
+fn tombstone_key(config: &AppConfig, uuid: &Uuid) -> String {
+    format!("{}deleted:{}", config.redis_prefix(), uuid.as_hyphenated())
+}
+
+/// Removes a text that was stored after it was deleted.
+async fn undo_deleted(cache: Option<deadpool_redis::Pool>, db: &Db, config: &AppConfig, text: &Text) {
+    let Some(cache) = cache else {
+        return;
+    };
+    let mut cache = match cache.get().await {
+        Ok(cache) => cache,
+        Err(error) => {
+            warn!("failed to check whether {} was deleted while written behind: {}", text._id, error);
+            return;
+        },
+    };
+    match observability::redis("EXISTS", cache.exists(tombstone_key(config, &text._id))).await {
+        Ok(false) => return,
+        Ok(true) => {},
+        Err(error) => {
+            warn!("failed to check whether {} was deleted while written behind: {}", text._id, error);
+            return;
+        },
+    }
+    // a text created anew since has been modified after this one
+    let filter = doc! { "_id": uuid_to_bson(&text._id), "modified_at": text.modified_at };
+    match observability::mongo("find_one_and_delete", config.texts(db).find_one_and_delete(filter, None)).await {
+        Ok(Some(text)) => {
+            discard(db, &text).await;
+            let _ = original::remove(db, text._id).await;
+        },
+        Ok(None) => {},
+        Err(error) => error!("failed to remove {}, which was deleted while written behind: {}", text._id, error),
+    }
+}
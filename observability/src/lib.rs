@@ -0,0 +1,31 @@
+//! Metrics and traces shared by the servers of this project, named alike so
+//! that one dashboard compares them.
+//!
+//! Each server hooks them into its own framework; the Rocket servers share the
+//! fairings and routes of [`rocket`], behind the feature of the same name.
+
+use std::fmt::Display;
+use std::future::IntoFuture;
+
+pub mod metrics;
+#[cfg(feature = "rocket")]
+pub mod rocket;
+pub mod trace;
+
+/// Times and traces a MongoDB operation.
+pub async fn mongo<F, T, E>(operation: &'static str, op: F) -> Result<T, E>
+where
+    F: IntoFuture<Output = Result<T, E>>,
+    E: Display,
+{
+    metrics::mongo(operation, trace::call("mongodb", operation, op)).await
+}
+
+/// Traces a Redis command.
+pub async fn redis<F, T, E>(command: &'static str, cmd: F) -> Result<T, E>
+where
+    F: IntoFuture<Output = Result<T, E>>,
+    E: Display,
+{
+    trace::call("redis", command, cmd).await
+}
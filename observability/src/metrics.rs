@@ -0,0 +1,102 @@
+//! Request and MongoDB metrics, kept in the default registry.
+//!
+//! Routes are labelled with their pattern, parameters written as `{name}`.
+
+use std::future::IntoFuture;
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, HistogramVec,
+    IntCounterVec, IntGauge,
+};
+
+/// Route label of requests that matched no route.
+pub const UNMATCHED: &str = "unmatched";
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Count handled HTTP requests",
+        &["method", "route", "status"]
+    )
+    .expect("Could not create lazy IntCounterVec")
+});
+
+pub static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to handle HTTP requests",
+        &["method", "route", "status"]
+    )
+    .expect("Could not create lazy HistogramVec")
+});
+
+pub static HTTP_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("http_requests_in_flight", "HTTP requests being handled")
+        .expect("Could not create lazy IntGauge")
+});
+
+pub static MONGO_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "mongodb_operation_duration_seconds",
+        "Time taken by MongoDB operations",
+        &["operation"]
+    )
+    .expect("Could not create lazy HistogramVec")
+});
+
+/// Cache lookups by `tier`, `redis` or `memory`, and outcome `type`, like `hit` or `miss`.
+pub static CACHE_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "cache_counter",
+        "Count hits and misses on cache",
+        &["tier", "type"]
+    )
+    .expect("Could not create lazy IntCounterVec")
+});
+
+/// Registers all metrics, so that they are exported before they are first used.
+pub fn register() {
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_DURATION);
+    Lazy::force(&HTTP_IN_FLIGHT);
+    Lazy::force(&MONGO_DURATION);
+    Lazy::force(&CACHE_COUNTER);
+}
+
+/// Counts a request as in flight until dropped, holding when it started.
+pub struct InFlight(Instant);
+
+impl InFlight {
+    pub fn start() -> InFlight {
+        HTTP_IN_FLIGHT.inc();
+        InFlight(Instant::now())
+    }
+
+    /// Counts and times the request once it was answered.
+    pub fn observe(&self, method: &str, route: &str, status: &str) {
+        let labels = [method, route, status];
+        HTTP_REQUESTS.with_label_values(&labels).inc();
+        HTTP_DURATION
+            .with_label_values(&labels)
+            .observe(self.0.elapsed().as_secs_f64());
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        HTTP_IN_FLIGHT.dec();
+    }
+}
+
+/// Times a MongoDB operation.
+pub async fn mongo<F: IntoFuture>(operation: &'static str, op: F) -> F::Output {
+    let _timer = MONGO_DURATION.with_label_values(&[operation]).start_timer();
+    op.await
+}
+
+/// The metrics of the default registry in the text format.
+pub fn export() -> prometheus::Result<String> {
+    prometheus::TextEncoder::new().encode_to_string(&prometheus::gather())
+}
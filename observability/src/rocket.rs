@@ -0,0 +1,151 @@
+//! Metrics and traces of the Rocket servers.
+//!
+//! A request counts as in flight from the time [`Track`] sees it until its
+//! response is sent. Fairings cannot follow the futures of handlers, so the
+//! routes themselves are wrapped by [`traced`] to run their handlers in the
+//! request's span.
+
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::FutureExt;
+use rocket::fairing::{self, AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, HeaderMap, Status};
+use rocket::outcome::Outcome;
+use rocket::route::{self, Handler};
+use rocket::{get, Build, Data, Request, Response, Rocket, Route};
+
+use crate::metrics::{self, InFlight, UNMATCHED};
+use crate::trace;
+
+/// Fairing counting and timing requests.
+pub struct Track;
+
+#[rocket::async_trait]
+impl Fairing for Track {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| Some(InFlight::start()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(in_flight) = req.local_cache(|| None::<InFlight>) else {
+            return;
+        };
+        let route = req
+            .route()
+            .map_or_else(|| UNMATCHED.to_owned(), |route| pattern(route.uri.path()));
+        let status = res.status().code.to_string();
+        in_flight.observe(req.method().as_str(), &route, &status);
+    }
+}
+
+/// Writes the parameters of a route as `{name}` rather than `<name>`.
+pub fn pattern(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix('<') {
+            Some(name) => format!("{{{}}}", name.trim_end_matches('>').trim_end_matches("..")),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[get("/metrics")]
+pub fn export() -> Result<(ContentType, String), Status> {
+    metrics::export()
+        .map(|metrics| (ContentType::Plain, metrics))
+        .map_err(|e| {
+            rocket::error!("failed to encode metrics: {e}");
+            Status::InternalServerError
+        })
+}
+
+/// Fairing exporting traces as `service`, flushing them on shutdown.
+pub fn tracing(service: &'static str) -> AdHoc {
+    AdHoc::try_on_ignite("Tracing", move |rocket| {
+        Box::pin(init_tracing(rocket, service))
+    })
+}
+
+async fn init_tracing(rocket: Rocket<Build>, service: &'static str) -> fairing::Result {
+    let provider = match trace::init(service) {
+        Ok(Some(provider)) => provider,
+        Ok(None) => return Ok(rocket),
+        Err(e) => {
+            rocket::error!("failed to create OTLP exporter: {e}");
+            return Err(rocket);
+        }
+    };
+    Ok(rocket.attach(AdHoc::on_shutdown("Flush traces", |_| {
+        Box::pin(async move {
+            if let Err(e) = trace::shutdown(provider).await {
+                rocket::error!("failed to flush traces: {e}");
+            }
+        })
+    })))
+}
+
+struct HeaderExtractor<'a> {
+    headers: &'a HeaderMap<'a>,
+    /// Header names, which the map only lends out with its headers.
+    keys: Vec<String>,
+}
+
+impl<'a> HeaderExtractor<'a> {
+    fn new(headers: &'a HeaderMap<'a>) -> HeaderExtractor<'a> {
+        let keys = headers
+            .iter()
+            .map(|header| header.name().to_string())
+            .collect();
+        HeaderExtractor { headers, keys }
+    }
+}
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.headers.get_one(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.keys.iter().map(String::as_str).collect()
+    }
+}
+
+/// Wraps the handlers of routes to trace their requests.
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(Traced(route.handler));
+            route
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct Traced(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for Traced {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let route = req.route().map(|route| pattern(route.uri.path()));
+        let cx = trace::start_request(
+            &HeaderExtractor::new(req.headers()),
+            req.method().as_str(),
+            route.as_deref(),
+            req.uri().path().as_str(),
+        );
+        let outcome = self.0.handle(req, data).with_context(cx.clone()).await;
+        let status = match &outcome {
+            Outcome::Success(response) => response.status(),
+            Outcome::Error(status) | Outcome::Forward((_, status)) => *status,
+        };
+        trace::end_request(&cx, status.code);
+        outcome
+    }
+}
@@ -0,0 +1,120 @@
+//! Traces exported over OTLP/HTTP to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//!
+//! Every request gets a span, continuing the trace of its W3C `traceparent`
+//! header, with a child span for each call to a database. Without an endpoint
+//! no traces are exported.
+
+use std::env;
+use std::fmt::Display;
+use std::future::IntoFuture;
+
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter};
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+/// Sets up exporting traces as `service` unless `OTEL_SERVICE_NAME` is set,
+/// returning the provider to [`shutdown`] once the server stopped.
+pub fn init(service: &'static str) -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none()
+        && env::var_os("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_none()
+    {
+        return Ok(None);
+    }
+    let exporter = SpanExporter::builder().with_http().build()?;
+    let mut resource = Resource::builder();
+    if env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(service);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// Flushes the spans not exported yet and stops exporting.
+///
+/// Runs on a blocking thread, as the exporter sends its last batch with a
+/// blocking client, which must not hold up the runtime.
+pub async fn shutdown(provider: SdkTracerProvider) -> OTelSdkResult {
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap_or_else(|err| Err(OTelSdkError::InternalFailure(err.to_string())))
+}
+
+fn tracer() -> BoxedTracer {
+    global::tracer(env!("CARGO_PKG_NAME"))
+}
+
+/// Starts the span of a request, as a child of the span its headers name.
+///
+/// `route` is the pattern of the matched route, its parameters written as `{name}`.
+pub fn start_request(
+    headers: &dyn Extractor,
+    method: &str,
+    route: Option<&str>,
+    path: &str,
+) -> Context {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(headers));
+    let name = match route {
+        Some(route) => format!("{method} {route}"),
+        None => method.to_owned(),
+    };
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", method.to_owned()),
+        KeyValue::new("url.path", path.to_owned()),
+    ];
+    attributes.extend(route.map(|route| KeyValue::new("http.route", route.to_owned())));
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &parent);
+    parent.with_span(span)
+}
+
+/// Ends the span of a request started by [`start_request`], marking server errors as failed.
+pub fn end_request(cx: &Context, status: u16) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new(
+        "http.response.status_code",
+        i64::from(status),
+    ));
+    if status >= 500 {
+        span.set_status(Status::error(status.to_string()));
+    }
+    span.end();
+}
+
+/// Traces a call to a database as a child of the current request.
+pub async fn call<F, T, E>(system: &'static str, operation: &'static str, call: F) -> Result<T, E>
+where
+    F: IntoFuture<Output = Result<T, E>>,
+    E: Display,
+{
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(format!("{system} {operation}"))
+        .with_kind(SpanKind::Client)
+        .with_attributes([
+            KeyValue::new("db.system.name", system),
+            KeyValue::new("db.operation.name", operation),
+        ])
+        .start(&tracer);
+    let cx = Context::current_with_span(span);
+    let result = call.into_future().with_context(cx.clone()).await;
+    if let Err(err) = &result {
+        cx.span().set_status(Status::error(err.to_string()));
+    }
+    cx.span().end();
+    result
+}
@@ -0,0 +1,274 @@
+//! The layouts the servers of this project store texts in, and the names
+//! MongoDB allows for their databases and collections.
+//!
+//! The validators of all layouts are kept side by side in `texts.json`. Every
+//! server checks its collection against its own [`Layout`] when it starts,
+//! running the commands built here with the MongoDB driver it depends on.
+
+use std::collections::HashMap;
+
+use bson::{doc, Bson, Document};
+
+/// Validators of the texts of all servers, by server name.
+const VALIDATORS: &str = include_str!("../texts.json");
+/// IDs as written by `uuid::serde::simple`, the same pattern as in the validator of axum.
+const SIMPLE_PATTERN: &str = "^[0-9a-f]{32}$";
+
+/// How a server writes the `_id` of a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Id {
+    /// The 16 bytes of the UUID as binary.
+    Binary,
+    /// The UUID as 32 lowercase hex digits.
+    Simple,
+}
+
+/// Where a server keeps the data of a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Data {
+    /// Always in this field.
+    In(&'static str),
+    /// Never in this field, which another server keeps its data in.
+    NotIn(&'static str),
+}
+
+/// How a server stores its texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Name of the server, which its validator is kept under.
+    pub server: &'static str,
+    pub id: Id,
+    pub data: Data,
+}
+
+pub const ACTIX: Layout = Layout {
+    server: "actix",
+    id: Id::Binary,
+    data: Data::In("data"),
+};
+pub const AXUM: Layout = Layout {
+    server: "axum",
+    id: Id::Simple,
+    data: Data::In("data"),
+};
+pub const ROCKET: Layout = Layout {
+    server: "rocket-text-searcher",
+    id: Id::Binary,
+    data: Data::In("text"),
+};
+/// Texts of the jakob server may keep their data in GridFS, shared content and more.
+pub const JAKOB: Layout = Layout {
+    server: "jakob-sample",
+    id: Id::Binary,
+    data: Data::NotIn("text"),
+};
+
+impl Layout {
+    /// The JSON schema texts are validated with.
+    pub fn validator(&self) -> Document {
+        let validators: HashMap<String, serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(VALIDATORS).expect("validators are valid JSON");
+        let validator = validators
+            .get(self.server)
+            .cloned()
+            .unwrap_or_else(|| panic!("{} has a validator", self.server));
+        Document::try_from(validator).expect("validator is a valid document")
+    }
+
+    /// A filter matching the texts stored in another layout.
+    pub fn incompatible(&self) -> Document {
+        let mut conditions = match self.id {
+            Id::Binary => vec![doc! { "_id": { "$not": { "$type": "binData" } } }],
+            Id::Simple => vec![
+                doc! { "_id": { "$not": { "$type": "string" } } },
+                doc! { "_id": { "$not": { "$regex": SIMPLE_PATTERN } } },
+            ],
+        };
+        conditions.push(match self.data {
+            Data::In(field) => doc! { field: { "$exists": false } },
+            Data::NotIn(field) => doc! { field: { "$exists": true } },
+        });
+        doc! { "$or": conditions }
+    }
+
+    /// Fails if a text was `found` by the [`incompatible`](Layout::incompatible) filter.
+    ///
+    /// Starting on top of such texts fails, rather than answering 404 or 500 for
+    /// each of them later.
+    pub fn check(&self, collection: &str, found: Option<&Document>) -> Result<(), String> {
+        match found {
+            None => Ok(()),
+            Some(text) => Err(format!(
+                "{collection} are stored in an incompatible layout, {}; expected {}",
+                self.describe(text),
+                self.expected()
+            )),
+        }
+    }
+
+    /// The command setting the validator of a collection, creating it unless it `exists`.
+    pub fn validate_command(&self, collection: &str, exists: bool) -> Document {
+        let command = if exists { "collMod" } else { "create" };
+        doc! {
+            command: collection,
+            "validator": self.validator(),
+            "validationLevel": "strict",
+            "validationAction": "error",
+        }
+    }
+
+    fn expected(&self) -> String {
+        let id = match self.id {
+            Id::Binary => "binary UUIDs",
+            Id::Simple => "simple UUID strings",
+        };
+        match self.data {
+            Data::In(field) => format!("{id} as _id and data in `{field}`"),
+            Data::NotIn(field) => format!("{id} as _id and no `{field}` field"),
+        }
+    }
+
+    /// What makes a text incompatible, for the error at startup.
+    fn describe(&self, text: &Document) -> String {
+        let Some(id) = text.get("_id") else {
+            return "a text has no _id".to_owned();
+        };
+        match (self.id, id) {
+            (Id::Binary, Bson::Binary(_)) => {}
+            (Id::Simple, Bson::String(simple)) if is_simple(simple) => {}
+            (Id::Simple, Bson::String(_)) => {
+                return format!("text {id} has an _id that is not a simple UUID")
+            }
+            _ => return format!("text {id} has an _id of type {:?}", id.element_type()),
+        }
+        match self.data {
+            Data::In(field) => format!("text {id} has no `{field}` field"),
+            Data::NotIn(field) => format!("text {id} keeps its data in `{field}`"),
+        }
+    }
+}
+
+fn is_simple(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Checks the restrictions MongoDB puts on the names of a database and a collection.
+pub fn check_names(database: &str, collection: &str) -> Result<(), String> {
+    if database.is_empty() || database.len() >= 64 {
+        return Err(format!(
+            "database name {database:?} must have 1 to 63 bytes"
+        ));
+    }
+    if let Some(c) = database
+        .chars()
+        .find(|c| r#"/\. "$*<>:|?"#.contains(*c) || *c == '\0')
+    {
+        return Err(format!("database name {database:?} must not contain {c:?}"));
+    }
+    if collection.is_empty() || collection.len() > 255 {
+        return Err(format!(
+            "collection name {collection:?} must have 1 to 255 bytes"
+        ));
+    }
+    if collection.contains(['$', '\0']) {
+        return Err(format!(
+            "collection name {collection:?} must not contain '$' or null"
+        ));
+    }
+    if collection.starts_with("system.") {
+        return Err(format!(
+            "collection name {collection:?} is reserved for MongoDB"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [Layout; 4] = [ACTIX, AXUM, ROCKET, JAKOB];
+
+    #[test]
+    fn every_layout_has_a_validator() {
+        for layout in LAYOUTS {
+            assert!(
+                layout.validator().contains_key("$jsonSchema"),
+                "{}",
+                layout.server
+            );
+        }
+    }
+
+    #[test]
+    fn validate_creates_missing_collections() {
+        let command = ACTIX.validate_command("texts", false);
+        assert_eq!(command.get_str("create"), Ok("texts"));
+        assert_eq!(command.get_document("validator"), Ok(&ACTIX.validator()));
+        let command = ACTIX.validate_command("texts", true);
+        assert_eq!(command.get_str("collMod"), Ok("texts"));
+    }
+
+    #[test]
+    fn compatible_texts_pass() {
+        assert_eq!(ACTIX.check("texts", None), Ok(()));
+    }
+
+    #[test]
+    fn describes_incompatible_texts() {
+        let binary = Bson::Binary(bson::Binary {
+            subtype: bson::spec::BinarySubtype::Generic,
+            bytes: vec![0; 16],
+        });
+        let simple = Bson::String("0123456789abcdef0123456789abcdef".to_owned());
+        let error =
+            |layout: Layout, text: Document| layout.check("texts", Some(&text)).unwrap_err();
+        assert!(error(ACTIX, doc! { "_id": &simple, "data": "a" })
+            .contains("has an _id of type String"));
+        assert!(error(ACTIX, doc! { "_id": &binary, "text": "a" }).contains("has no `data` field"));
+        assert!(error(ROCKET, doc! { "_id": &binary, "data": "a" }).contains("has no `text` field"));
+        assert!(
+            error(JAKOB, doc! { "_id": &binary, "text": "a" }).contains("keeps its data in `text`")
+        );
+        assert!(
+            error(AXUM, doc! { "_id": "0123-4567", "data": "a" }).contains("is not a simple UUID")
+        );
+        assert!(error(AXUM, doc! { "_id": &simple }).contains("has no `data` field"));
+        assert!(error(AXUM, doc! {}).contains("a text has no _id"));
+        assert!(error(AXUM, doc! { "_id": &simple })
+            .ends_with("expected simple UUID strings as _id and data in `data`"));
+    }
+
+    #[test]
+    fn incompatible_filters() {
+        assert_eq!(
+            JAKOB.incompatible(),
+            doc! { "$or": [{ "_id": { "$not": { "$type": "binData" } } }, { "text": { "$exists": true } }] }
+        );
+        assert_eq!(
+            AXUM.incompatible(),
+            doc! {
+                "$or": [
+                    { "_id": { "$not": { "$type": "string" } } },
+                    { "_id": { "$not": { "$regex": SIMPLE_PATTERN } } },
+                    { "data": { "$exists": false } },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(check_names("erfa", "texts"), Ok(()));
+        assert_eq!(check_names(&"d".repeat(63), &"c".repeat(255)), Ok(()));
+        assert!(check_names("", "texts").is_err());
+        assert!(check_names(&"d".repeat(64), "texts").is_err());
+        assert!(check_names("my.db", "texts").is_err());
+        assert!(check_names("my db", "texts").is_err());
+        assert!(check_names("erfa", "").is_err());
+        assert!(check_names("erfa", &"c".repeat(256)).is_err());
+        assert!(check_names("erfa", "te$ts").is_err());
+        assert!(check_names("erfa", "system.texts").is_err());
+        assert_eq!(check_names("erfa", "tenant.texts"), Ok(()));
+    }
+}
@@ -0,0 +1,111 @@
+#!/usr/bin/env bash
+# Checks that a server exports traces of its requests, continuing the trace of the caller.
+#
+# Starts a stand-in for an OTLP/HTTP collector, which keeps the spans posted to it, and the
+# server with its exporter pointed there. Then creates and reads a text as part of a trace
+# started here and looks for that trace, the request spans and the MongoDB span among the
+# exported ones. With EXPECT_REDIS=1, the server has to have traced Redis calls too.
+#
+#   ./trace-check.sh http://127.0.0.1:8080 -- cargo run --manifest-path frameworks/actix/Cargo.toml
+#   EXPECT_REDIS=1 ./trace-check.sh http://127.0.0.1:8000 -- ./jakob-sample/target/debug/ho-erfa-sample
+set -euo pipefail
+
+if [ $# -lt 3 ] || [ "$2" != "--" ]; then
+    echo "usage: $0 <server url> -- <server command>..." >&2
+    exit 2
+fi
+URL=$1
+shift 2
+COLLECTOR_PORT=${COLLECTOR_PORT:-4319}
+
+work=$(mktemp -d)
+pids=()
+cleanup() {
+    for pid in "${pids[@]}"; do kill "$pid" 2>/dev/null || true; done
+    wait 2>/dev/null || true
+    rm -rf "$work"
+}
+trap cleanup EXIT
+
+wait_for() {
+    for _ in $(seq 300); do
+        if "$@" >/dev/null 2>&1; then return 0; fi
+        sleep 0.1
+    done
+    echo "timed out waiting for: $*" >&2
+    return 1
+}
+
+# answers every export with success, appending the protobuf payloads to $work/spans
+python3 - "$COLLECTOR_PORT" "$work/spans" >"$work/collector.log" 2>&1 <<'EOF' &
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+port, spans = int(sys.argv[1]), sys.argv[2]
+
+class Collector(BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        if self.path == "/v1/traces":
+            with open(spans, "ab") as f:
+                f.write(body)
+        self.send_response(200)
+        self.send_header("Content-Type", "application/x-protobuf")
+        self.send_header("Content-Length", "0")
+        self.end_headers()
+
+HTTPServer(("127.0.0.1", port), Collector).serve_forever()
+EOF
+pids+=($!)
+
+OTEL_EXPORTER_OTLP_ENDPOINT=http://127.0.0.1:$COLLECTOR_PORT \
+OTEL_EXPORTER_OTLP_PROTOCOL=http/protobuf \
+OTEL_BSP_SCHEDULE_DELAY=200 \
+    "$@" >"$work/server.log" 2>&1 &
+pids+=($!)
+wait_for curl -sf "$URL/metrics"
+
+trace_id=$(od -An -N16 -tx1 /dev/urandom | tr -d ' \n')
+parent_id=$(od -An -N8 -tx1 /dev/urandom | tr -d ' \n')
+traceparent="00-$trace_id-$parent_id-01"
+
+id=$(curl -sf -X POST "$URL/texts" -H "traceparent: $traceparent" -H 'Content-Type: application/json' \
+    -d '{"data":"hello traces"}' | sed -n 's/.*"id":"\([^"]*\)".*/\1/p')
+if [ -z "$id" ]; then
+    echo "failed to create a text, server log:" >&2
+    cat "$work/server.log" >&2
+    exit 1
+fi
+curl -sf -o /dev/null "$URL/texts/$id" -H "traceparent: $traceparent"
+curl -sf -o /dev/null "$URL/texts/$id" -H "traceparent: $traceparent"
+
+# the spans are found in the raw payloads, where IDs are stored as bytes and names as strings
+python3 - "$work/spans" "$trace_id" "$parent_id" "${EXPECT_REDIS:-0}" <<'EOF'
+import sys, time
+
+spans, trace_id, parent_id, expect_redis = sys.argv[1:]
+expected = {
+    "trace of the caller": bytes.fromhex(trace_id),
+    "span of the caller as parent": bytes.fromhex(parent_id),
+    "request span creating the text": b"POST /texts",
+    "request span reading the text": b"GET /texts/{",
+    "MongoDB span": b"mongodb insert_one",
+}
+if expect_redis == "1":
+    expected["Redis span"] = b"redis "
+
+missing = expected
+for _ in range(50):
+    try:
+        data = open(spans, "rb").read()
+    except FileNotFoundError:
+        data = b""
+    missing = {name: needle for name, needle in expected.items() if needle not in data}
+    if not missing:
+        break
+    time.sleep(0.2)
+for name in expected:
+    print(("FAIL " if name in missing else "ok   ") + name)
+sys.exit(1 if missing else 0)
+EOF
+echo "all checks passed"